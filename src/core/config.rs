@@ -1,30 +1,43 @@
-use std::path::PathBuf;
-
 #[cfg(feature = "cli")]
 use clap::{ArgAction, CommandFactory, Parser};
 #[cfg(feature = "cli")]
 use clap_complete::{generate, Generator, Shell};
-use lazy_static::lazy_static;
+use std::path::PathBuf;
 
-lazy_static! {
-    pub static ref CFG: Config = Config::new();
-}
+use anyhow::{bail, Context};
+
+use crate::core::{
+    options::{OutputOptions, SearchOptions, WalkOptions},
+    set::Pattern,
+};
 
 #[derive(Debug, Default)]
 #[cfg_attr(feature = "cli", derive(Parser))]
 #[cfg_attr(feature = "cli", command(author, version, about, long_about = None))]
 pub struct Config {
-    pub expr: String,
+    /// the expression to search for, or the first path if patterns are given with -e or -f
+    pub expr: Option<String>,
 
     pub paths: Vec<PathBuf>,
 
+    /// search for every one of these expressions at once
+    #[cfg_attr(feature = "cli", arg(long = "expr", short = 'e', value_name = "EXPR"))]
+    pub exprs: Vec<String>,
+
+    /// read expressions from a pattern file
+    #[cfg_attr(feature = "cli", arg(long = "file", short = 'f', value_name = "FILE"))]
+    pub pattern_files: Vec<PathBuf>,
+
+    /// only search for the patterns with these names
+    #[cfg_attr(feature = "cli", arg(long, value_name = "NAME"))]
+    pub select: Vec<String>,
+
     #[cfg_attr(feature = "cli", arg(long, default_value_t = false))]
     pub dbg_expr_tree: bool,
 
     #[cfg_attr(feature = "cli", arg(long, short, default_value_t = 1))]
     pub space: u32,
 
-    // FIXME do not check pretty flag every time we write...
     #[cfg_attr(feature = "cli", arg(long="no-pretty", short, default_value_t = true, action = ArgAction::SetFalse))]
     pub pretty: bool,
 
@@ -34,20 +47,155 @@ pub struct Config {
     #[cfg_attr(feature = "cli", arg(long, short = 'n'))]
     pub stop_after: Option<usize>,
 
+    /// only report matches that start at a multiple of n
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))
+    )]
+    pub align: Option<u64>,
+
+    /// write every match as a line of json
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = false))]
+    pub json: bool,
+
+    /// search the files in directories and their subdirectories
+    #[cfg_attr(feature = "cli", arg(long, short, default_value_t = false))]
+    pub recursive: bool,
+
+    /// only search files matching the glob when searching recursively
+    #[cfg_attr(feature = "cli", arg(long, value_name = "GLOB"))]
+    pub include: Vec<String>,
+
+    /// skip files and directories matching the glob when searching recursively
+    #[cfg_attr(feature = "cli", arg(long, value_name = "GLOB"))]
+    pub exclude: Vec<String>,
+
+    /// follow symbolic links when searching recursively
+    #[cfg_attr(feature = "cli", arg(long, short = 'L', default_value_t = false))]
+    pub follow: bool,
+
+    /// do not descend more than n directories when searching recursively
+    #[cfg_attr(feature = "cli", arg(long, value_name = "N"))]
+    pub max_depth: Option<usize>,
+
+    /// amount of files searched at the same time, 0 uses every core
+    #[cfg_attr(
+        feature = "cli",
+        arg(long = "threads", short = 'j', default_value_t = 0)
+    )]
+    pub threads: usize,
+
+    /// print files as soon as they are done instead of in the order they were given
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = false))]
+    pub unordered: bool,
+
+    /// print nothing and stop after the first match
+    #[cfg_attr(
+        feature = "cli",
+        arg(long, short, visible_alias = "silent", default_value_t = false)
+    )]
+    pub quiet: bool,
+
+    /// do not report files that could not be searched
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = false))]
+    pub no_messages: bool,
+
+    /// always stream files instead of memory mapping them
+    #[cfg_attr(feature = "cli", arg(long, default_value_t = false))]
+    pub no_mmap: bool,
+
     #[cfg_attr(feature = "cli", clap(long, value_name = "SHELL"))]
     #[cfg(feature = "cli")]
     pub completions: Option<Shell>,
 }
 
 impl Config {
-    #[cfg(all(feature = "cli", not(test)))]
+    #[cfg(feature = "cli")]
     pub fn new() -> Self {
         Self::parse()
     }
-    #[cfg(any(test, not(feature = "cli")))]
+    #[cfg(not(feature = "cli"))]
     pub fn new() -> Self {
         Default::default()
     }
+
+    // true if patterns are not given as the first argument
+    fn has_pattern_options(&self) -> bool {
+        !self.exprs.is_empty() || !self.pattern_files.is_empty()
+    }
+
+    // every pattern that should be searched for
+    pub fn patterns(&self) -> anyhow::Result<Vec<Pattern>> {
+        if !self.has_pattern_options() {
+            return match &self.expr {
+                Some(expr) => Ok(vec![Pattern::new(expr, expr)]),
+                None => bail!("no expression given"),
+            };
+        }
+
+        let mut patterns: Vec<Pattern> = self.exprs.iter().map(|e| Pattern::new(e, e)).collect();
+        for path in &self.pattern_files {
+            let src = std::fs::read_to_string(path).with_context(|| path.display().to_string())?;
+            patterns.extend(Pattern::parse_list(&src).with_context(|| path.display().to_string())?);
+        }
+
+        if self.select.is_empty() {
+            return Ok(patterns);
+        }
+        for name in &self.select {
+            if !patterns.iter().any(|p| &p.name == name) {
+                bail!("unknown pattern {name}");
+            }
+        }
+        patterns.retain(|p| self.select.contains(&p.name));
+        Ok(patterns)
+    }
+
+    // the paths to search
+    pub fn paths(&self) -> Vec<PathBuf> {
+        match &self.expr {
+            Some(expr) if self.has_pattern_options() => std::iter::once(PathBuf::from(expr))
+                .chain(self.paths.iter().cloned())
+                .collect(),
+            _ => self.paths.clone(),
+        }
+    }
+
+    // the amount of worker threads to use
+    pub fn threads(&self) -> usize {
+        if self.threads != 0 {
+            self.threads
+        } else {
+            std::thread::available_parallelism().map_or(1, |n| n.get())
+        }
+    }
+
+    pub fn search_options(&self) -> SearchOptions {
+        SearchOptions {
+            stop_after: if self.quiet { Some(1) } else { self.stop_after },
+            align: self.align.map(|align| align as usize),
+        }
+    }
+
+    pub fn walk_options(&self) -> WalkOptions {
+        WalkOptions {
+            recursive: self.recursive,
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+            follow: self.follow,
+            max_depth: self.max_depth,
+        }
+    }
+
+    pub fn output_options(&self) -> OutputOptions {
+        OutputOptions {
+            pretty: self.pretty,
+            count: self.count,
+            space: self.space,
+            quiet: self.quiet,
+            json: self.json,
+        }
+    }
 }
 
 #[cfg(feature = "cli")]
@@ -59,3 +207,43 @@ pub fn generate_completion<G: Generator>(gen: G) {
         &mut std::io::stdout(),
     );
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        let cfg = Config {
+            expr: Some("30".into()),
+            paths: vec!["a".into()],
+            ..Default::default()
+        };
+        assert_eq!(vec![Pattern::new("30", "30")], cfg.patterns().unwrap());
+        assert_eq!(vec![PathBuf::from("a")], cfg.paths());
+
+        let cfg = Config {
+            exprs: vec!["30".into(), "31".into()],
+            ..cfg
+        };
+        assert_eq!(2, cfg.patterns().unwrap().len());
+        assert_eq!(vec![PathBuf::from("30"), PathBuf::from("a")], cfg.paths());
+
+        assert!(Config::default().patterns().is_err());
+
+        let cfg = Config {
+            select: vec!["31".into()],
+            ..cfg
+        };
+        assert_eq!(vec![Pattern::new("31", "31")], cfg.patterns().unwrap());
+
+        let cfg = Config {
+            select: vec!["32".into()],
+            ..cfg
+        };
+        assert_eq!(
+            "unknown pattern 32",
+            cfg.patterns().unwrap_err().to_string()
+        );
+    }
+}
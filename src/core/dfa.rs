@@ -0,0 +1,598 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::core::{
+    error::{Error, RbrepResult},
+    input::{MatchInput, DEFAULT_CHUNK_SIZE},
+    program::{Inst, Program},
+};
+
+// how many states are cached before the dfa gives up and the vm takes over
+const MAX_STATES: usize = 4096;
+
+// how many threads all cached states may hold together
+// wide repetitions build a few states with a huge amount of threads each
+const MAX_THREADS: usize = 1 << 22;
+
+// transition that was not computed yet
+const UNKNOWN: u32 = u32::MAX;
+
+// a set of byte values
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+struct ByteSet([u64; 4]);
+
+impl ByteSet {
+    fn from_fn(f: impl Fn(u8) -> bool) -> Self {
+        let mut set = Self::default();
+        for b in 0..=255 {
+            if f(b) {
+                set.0[b as usize / 64] |= 1 << (b % 64);
+            }
+        }
+        set
+    }
+
+    fn contains(&self, b: u8) -> bool {
+        self.0[b as usize / 64] & (1 << (b % 64)) != 0
+    }
+
+    fn union(&self, other: &Self) -> Self {
+        Self(std::array::from_fn(|i| self.0[i] | other.0[i]))
+    }
+
+    fn complement(&self) -> Self {
+        Self(self.0.map(|x| !x))
+    }
+}
+
+// Nodes that a closure already visited.
+// Clearing only starts a new generation instead of touching every node.
+struct Seen {
+    marks: Vec<u32>,
+    generation: u32,
+}
+
+impl Seen {
+    fn new(len: usize) -> Self {
+        Self {
+            marks: vec![0; len],
+            generation: 1,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+        if self.generation == 0 {
+            self.marks.fill(0);
+            self.generation = 1;
+        }
+    }
+
+    // false if pc was seen already
+    fn insert(&mut self, pc: usize) -> bool {
+        let new = self.marks[pc] != self.generation;
+        self.marks[pc] = self.generation;
+        new
+    }
+
+    fn contains(&self, pc: usize) -> bool {
+        self.marks[pc] == self.generation
+    }
+}
+
+#[derive(Clone)]
+enum Node {
+    // consumes a single byte in the set
+    Class(ByteSet),
+    Split(usize, usize),
+    Jmp(usize),
+    Fail,
+    Match,
+}
+
+// A program where every instruction consumes at most a single byte.
+// It is turned into a dfa lazily while searching, see DfaCache.
+// Only programs without lookahead that spans more than one byte can be compiled,
+// everything else has to be run by the backtracking vm.
+#[derive(Clone)]
+pub struct Dfa {
+    nodes: Vec<Node>,
+    // a thread that consumed a byte may continue where a new match starts,
+    // the threads of a state then no longer tell which starts are still alive
+    rejoins: bool,
+}
+
+impl Dfa {
+    pub fn new(prog: &Program) -> Option<Self> {
+        let insts = prog.insts();
+
+        // literals and integers turn into one node per byte
+        // so every instruction has to be moved
+        let mut starts = Vec::with_capacity(insts.len());
+        let mut len = 0;
+        for inst in insts {
+            starts.push(len);
+            len += match Self::sequences(inst) {
+                Some(seqs) => {
+                    seqs.iter().map(|seq| seq.len()).sum::<usize>() + 2 * (seqs.len() - 1)
+                }
+                None => 1,
+            };
+        }
+
+        let mut nodes = Vec::with_capacity(len);
+        for (pc, inst) in insts.iter().enumerate() {
+            if let Some(seqs) = Self::sequences(inst) {
+                // each sequence but the last one is a split to the next
+                let end = starts.get(pc + 1).copied().unwrap_or(len);
+                for (i, seq) in seqs.iter().enumerate() {
+                    let last = i + 1 == seqs.len();
+                    if !last {
+                        nodes.push(Node::Split(nodes.len() + 1, nodes.len() + seq.len() + 2));
+                    }
+                    nodes.extend(seq.iter().map(|class| Node::Class(*class)));
+                    if !last {
+                        nodes.push(Node::Jmp(end));
+                    }
+                }
+                continue;
+            }
+
+            let class = match inst {
+                Inst::Byte(value) => ByteSet::from_fn(|b| b == *value),
+                Inst::And(value) => ByteSet::from_fn(|b| b & value != 0),
+                Inst::Mask { mask, value } => ByteSet::from_fn(|b| b & mask == *value),
+                Inst::Range { from, to, mask } => {
+                    ByteSet::from_fn(|b| (*from..*to).contains(&(b & mask)))
+                }
+                Inst::Any => ByteSet::from_fn(|_| true),
+                Inst::Not(prog) => Self::new(prog)?.single_class()?.complement(),
+                Inst::Literal(_) | Inst::Int { .. } => unreachable!("sequences are compiled above"),
+                // the dfa only knows about bytes, not where they are
+                Inst::Start
+                | Inst::End
+                | Inst::Align(_)
+                | Inst::Backref(_)
+                | Inst::ReadLen { .. }
+                | Inst::Skip(_)
+                | Inst::Bound(_)
+                | Inst::CheckEnd(_)
+                | Inst::At { .. } => return None,
+                Inst::Split(a, b) => {
+                    nodes.push(Node::Split(starts[*a], starts[*b]));
+                    continue;
+                }
+                Inst::Jmp(to) => {
+                    nodes.push(Node::Jmp(starts[*to]));
+                    continue;
+                }
+                // captures do not change what matches
+                Inst::Save(_) => {
+                    nodes.push(Node::Jmp(nodes.len() + 1));
+                    continue;
+                }
+                Inst::Fail => {
+                    nodes.push(Node::Fail);
+                    continue;
+                }
+                Inst::Match => {
+                    nodes.push(Node::Match);
+                    continue;
+                }
+            };
+            nodes.push(Node::Class(class));
+        }
+
+        let mut dfa = Self {
+            nodes,
+            rejoins: false,
+        };
+        let mut first = vec![];
+        dfa.closure(0, &mut first, &mut Seen::new(dfa.nodes.len()), true);
+        let mut seen = Seen::new(dfa.nodes.len());
+        for pc in 0..dfa.nodes.len() {
+            if matches!(dfa.nodes[pc], Node::Class(_)) {
+                dfa.closure(pc + 1, &mut vec![], &mut seen, true);
+            }
+        }
+        dfa.rejoins = first.iter().any(|pc| seen.contains(*pc as usize));
+        Some(dfa)
+    }
+
+    // literals and integers are alternatives of byte sequences
+    fn sequences(inst: &Inst) -> Option<Vec<Vec<ByteSet>>> {
+        match inst {
+            Inst::Literal(value) => Some(vec![value
+                .iter()
+                .map(|v| ByteSet::from_fn(|b| b == *v))
+                .collect()]),
+            Inst::Int { ty, from, to } => Some(
+                ty.byte_ranges(*from, *to)
+                    .iter()
+                    .map(|seq| {
+                        seq.iter()
+                            .map(|(lo, hi)| ByteSet::from_fn(|b| (*lo..=*hi).contains(&b)))
+                            .collect()
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    // Follow every split and jump starting at pc and add all nodes that
+    // consume a byte to out in the order the vm would try them.
+    // Returns true if a match is reached, lower priority paths are dropped in that case.
+    // If skip_match is set a match is ignored instead.
+    fn closure(&self, pc: usize, out: &mut Vec<u32>, seen: &mut Seen, skip_match: bool) -> bool {
+        let mut stack = vec![pc];
+        while let Some(pc) = stack.pop() {
+            if !seen.insert(pc) {
+                continue;
+            }
+
+            match &self.nodes[pc] {
+                Node::Class(_) => out.push(pc as u32),
+                Node::Split(a, b) => {
+                    stack.push(*b);
+                    stack.push(*a);
+                }
+                Node::Jmp(to) => stack.push(*to),
+                Node::Fail => {}
+                Node::Match => {
+                    if !skip_match {
+                        return true;
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    // the set of bytes this program matches
+    // if it only ever decides on a single byte
+    fn single_class(&self) -> Option<ByteSet> {
+        let mut first = vec![];
+        if self.closure(0, &mut first, &mut Seen::new(self.nodes.len()), false) {
+            // an empty match matches everything
+            return Some(ByteSet::from_fn(|_| true));
+        }
+
+        let mut set = ByteSet::default();
+        for pc in first {
+            let mut next = vec![];
+            let matched = self.closure(
+                pc as usize + 1,
+                &mut next,
+                &mut Seen::new(self.nodes.len()),
+                false,
+            );
+            match &self.nodes[pc as usize] {
+                Node::Class(class) if matched => set = set.union(class),
+                // this would depend on the next byte
+                _ if !next.is_empty() => return None,
+                _ => {}
+            }
+        }
+        Some(set)
+    }
+}
+
+// What an unanchored search found out about the rest of the input.
+#[derive(Debug, PartialEq, Eq)]
+pub enum EarliestEnd {
+    // offset from the current position at which the first match ends
+    // and the offset before which no match starts
+    At { dead: usize, end: usize },
+    // nothing can match in the rest of the input
+    Never,
+    // the search has to go on later, no match starts before the offset
+    Pending(usize),
+}
+
+struct State {
+    // sorted, the order of the threads does not change if a match is reached
+    threads: Arc<[u32]>,
+    // a match ends right before the byte that leads into this state
+    is_match: bool,
+    next: Box<[u32; 256]>,
+}
+
+// The lazily built states of a dfa.
+// Every search owns its own cache, states are only computed when they are reached.
+// Once the states grow too large the cache gives up
+// and leaves every decision to the vm.
+pub struct DfaCache<'d> {
+    dfa: &'d Dfa,
+    states: Vec<State>,
+    ids: HashMap<(Arc<[u32]>, bool, bool), u32>,
+    // threads of all states together
+    threads: usize,
+    full: bool,
+    seen: Seen,
+    // anchored and unanchored start states
+    starts: [Option<u32>; 2],
+    // state and offset of the next byte of an unanchored search that ran out of input
+    scan: Option<(u32, usize)>,
+}
+
+impl<'d> DfaCache<'d> {
+    pub fn new(dfa: &'d Dfa) -> Self {
+        Self {
+            dfa,
+            states: vec![],
+            ids: HashMap::new(),
+            threads: 0,
+            full: false,
+            seen: Seen::new(dfa.nodes.len()),
+            starts: [None, None],
+            scan: None,
+        }
+    }
+
+    // true if the dfa gave up, nothing it says rules out a match any more
+    pub fn is_full(&self) -> bool {
+        self.full
+    }
+
+    // false if no non-empty match starts at the current position of the input
+    // true if one does or if the dfa gave up
+    pub fn is_match<IF: MatchInput>(&mut self, i: &mut IF) -> RbrepResult<bool> {
+        let Some(mut state) = self.start(false) else {
+            return Ok(true);
+        };
+        let mut pos = 0;
+        while !self.states[state as usize].threads.is_empty() {
+            let b = match i.read(pos) {
+                Err(Error::EndOfFile) => return Ok(false),
+                b => b?,
+            };
+            let Some(next) = self.next(state, b, false) else {
+                return Ok(true);
+            };
+            state = next;
+            pos += 1;
+            if self.states[state as usize].is_match {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    // Find the earliest offset at which a match that starts
+    // at or after the current position ends.
+    // Only what is already buffered and at most another chunk is looked at
+    // so that a stream can be dropped while it is searched,
+    // the next call picks the search up where this one stopped.
+    pub fn earliest_end<IF: MatchInput>(&mut self, i: &mut IF) -> RbrepResult<EarliestEnd> {
+        let Some(start) = self.start(true) else {
+            return Ok(EarliestEnd::Pending(0));
+        };
+        let limit = i.window(DEFAULT_CHUNK_SIZE)?.len();
+
+        // a search that started before the current position
+        // finds every match that starts at it as well
+        let base = i.pos();
+        let (mut state, mut pos) = match self.scan.take() {
+            Some((state, at)) if at >= base => (state, at - base),
+            _ => (start, 0),
+        };
+        // every match that started before this offset failed already
+        let mut dead = 0;
+        while !self.states[state as usize].threads.is_empty() {
+            if !self.dfa.rejoins && state == start {
+                dead = pos;
+            }
+            let b = match i.read(pos) {
+                Err(Error::EndOfFile) => return Ok(EarliestEnd::Never),
+                b => b?,
+            };
+            if pos >= limit {
+                self.scan = Some((state, base + pos));
+                return Ok(EarliestEnd::Pending(dead));
+            }
+            let Some(next) = self.next(state, b, true) else {
+                return Ok(EarliestEnd::Pending(dead));
+            };
+            state = next;
+            pos += 1;
+            if self.states[state as usize].is_match {
+                return Ok(EarliestEnd::At { dead, end: pos });
+            }
+        }
+        Ok(EarliestEnd::Never)
+    }
+
+    fn start(&mut self, unanchored: bool) -> Option<u32> {
+        if let Some(id) = self.starts[unanchored as usize] {
+            return Some(id);
+        }
+
+        let mut threads = vec![];
+        // empty matches are never reported
+        self.seen.clear();
+        self.dfa.closure(0, &mut threads, &mut self.seen, true);
+        let id = self.add(threads, false, unanchored)?;
+        self.starts[unanchored as usize] = Some(id);
+        Some(id)
+    }
+
+    // the state after b or None once the dfa gave up
+    fn next(&mut self, id: u32, b: u8, unanchored: bool) -> Option<u32> {
+        let next = self.states[id as usize].next[b as usize];
+        if next != UNKNOWN {
+            return Some(next);
+        }
+
+        let nodes = &self.dfa.nodes;
+        self.seen.clear();
+        let mut threads = vec![];
+        let mut is_match = false;
+        for pc in self.states[id as usize].threads.iter() {
+            let accepts = matches!(&nodes[*pc as usize], Node::Class(class) if class.contains(b));
+            if accepts
+                && self
+                    .dfa
+                    .closure(*pc as usize + 1, &mut threads, &mut self.seen, false)
+            {
+                is_match = true;
+                break;
+            }
+        }
+
+        // a new match may start at every offset
+        if unanchored && !is_match {
+            self.dfa.closure(0, &mut threads, &mut self.seen, true);
+        }
+
+        let next = self.add(threads, is_match, unanchored)?;
+        self.states[id as usize].next[b as usize] = next;
+        Some(next)
+    }
+
+    // the id of the state with these threads
+    // None once too many states were built
+    fn add(&mut self, mut threads: Vec<u32>, is_match: bool, unanchored: bool) -> Option<u32> {
+        if self.full {
+            return None;
+        }
+
+        threads.sort_unstable();
+        let key = (Arc::from(threads), is_match, unanchored);
+        if let Some(id) = self.ids.get(&key) {
+            return Some(*id);
+        }
+
+        self.threads += key.0.len();
+        if self.states.len() >= MAX_STATES || self.threads > MAX_THREADS {
+            // the memory is better spent on the vm
+            self.full = true;
+            self.states = vec![];
+            self.ids = HashMap::new();
+            self.starts = [None, None];
+            self.scan = None;
+            return None;
+        }
+
+        let id = self.states.len() as u32;
+        self.states.push(State {
+            threads: key.0.clone(),
+            is_match,
+            next: Box::new([UNKNOWN; 256]),
+        });
+        self.ids.insert(key, id);
+        Some(id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::{expr::Expr, input::FileBufferInput, output::ExprOutput};
+
+    fn dfa(expr: &str) -> Option<Dfa> {
+        Dfa::new(&Program::compile(&Expr::tree_from(expr).unwrap()))
+    }
+
+    #[test]
+    fn compile() {
+        assert!(dfa("30 \"AB\"{1,3}? (31 ??) u16le:5").is_some());
+        assert!(dfa("!(30 31-33)").is_some());
+        assert!(dfa("!\"AB\"").is_none());
+        assert!(dfa("u16le:5-10 i64be:-1-0x100").is_some());
+        assert!(dfa("!u16le:5-10").is_none());
+        assert!(dfa("{a: 30} \\a").is_none());
+        assert!(dfa("@len(u8)").is_none());
+        assert!(dfa("^ 30").is_none());
+    }
+
+    #[test]
+    fn same_as_vm() {
+        let exprs = [
+            "30 31",
+            "30 ??*0+; 32",
+            "30 ??{0,}? 32",
+            "(30 &(3031)) 32",
+            "31{1,3} 31",
+            "(30{0,1}){0,} 31",
+            "!(30 31){2}",
+            "!!30",
+            "\"01\" 3?",
+            "30{0,1}",
+            "{a: 30} ??{0,}? {b: 32}",
+            "u16le:0x3031-0x3230",
+            "i16be:-0x3000-0x3031 ??",
+            "!u8:0x31-0x32",
+        ];
+        let input = b"0120011023013201";
+        for expr in exprs {
+            let prog = Program::compile(&Expr::tree_from(expr).unwrap());
+            let dfa = Dfa::new(&prog).unwrap();
+            let mut cache = DfaCache::new(&dfa);
+            for start in 0..input.len() {
+                let mut i = FileBufferInput::new(&input[start..]);
+                let vm = prog
+                    .exec(&mut i, &mut ExprOutput::default(), false)
+                    .unwrap();
+                assert_eq!(vm, cache.is_match(&mut i).unwrap(), "{expr} at {start}");
+            }
+        }
+    }
+
+    #[test]
+    fn earliest_end() {
+        let scan = dfa("30 ??{0,} 32").unwrap();
+        let mut cache = DfaCache::new(&scan);
+        let mut i = FileBufferInput::new(b"1101132012".as_slice());
+        assert_eq!(
+            EarliestEnd::At { dead: 2, end: 7 },
+            cache.earliest_end(&mut i).unwrap()
+        );
+        i.advance(7).unwrap();
+        assert_eq!(
+            EarliestEnd::At { dead: 0, end: 3 },
+            cache.earliest_end(&mut i).unwrap()
+        );
+        i.advance(3).unwrap();
+        assert_eq!(EarliestEnd::Never, cache.earliest_end(&mut i).unwrap());
+
+        // a long search is split up
+        let mut data = vec![b'1'; DEFAULT_CHUNK_SIZE * 3];
+        data.extend(b"012");
+        let mut i = FileBufferInput::new(data.as_slice());
+        let mut end = cache.earliest_end(&mut i).unwrap();
+        assert_eq!(EarliestEnd::Pending(DEFAULT_CHUNK_SIZE), end);
+        while let EarliestEnd::Pending(start) = end {
+            i.advance(start.max(1)).unwrap();
+            end = cache.earliest_end(&mut i).unwrap();
+        }
+        let end_at = data.len() - i.pos();
+        assert_eq!(
+            EarliestEnd::At {
+                dead: end_at - 3,
+                end: end_at
+            },
+            end
+        );
+
+        // a thread that loops back to the start keeps every start alive
+        let looping = dfa("??*0+; 32").unwrap();
+        let mut cache = DfaCache::new(&looping);
+        let mut i = FileBufferInput::new(data.as_slice());
+        assert_eq!(EarliestEnd::Pending(0), cache.earliest_end(&mut i).unwrap());
+    }
+
+    #[test]
+    fn gives_up() {
+        let wide = dfa("30 ??{0,5000} 32").unwrap();
+        let mut cache = DfaCache::new(&wide);
+        let mut data = vec![b'0'];
+        data.resize(5000, b'1');
+        data.push(b'2');
+
+        // too many threads, the vm has to decide
+        let mut i = FileBufferInput::new(data.as_slice());
+        assert!(cache.is_match(&mut i).unwrap());
+        assert!(cache.is_full());
+        assert_eq!(EarliestEnd::Pending(0), cache.earliest_end(&mut i).unwrap());
+    }
+}
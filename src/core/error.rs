@@ -1,18 +1,108 @@
+use std::fmt::Display;
+
 use thiserror::Error;
 
 pub type RbrepResult<T> = Result<T, Error>;
 
+// A syntax error in an expression.
+// start and end are char offsets into src.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub src: String,
+    pub start: usize,
+    pub end: usize,
+    pub expected: String,
+}
+
+impl SyntaxError {
+    pub fn new(src: &str, start: usize, end: usize, expected: &str) -> Self {
+        Self {
+            src: src.into(),
+            start,
+            end: end.max(start + 1),
+            expected: expected.into(),
+        }
+    }
+
+    // line and column of the start of the error, both start at 1
+    pub fn line_col(&self) -> (usize, usize) {
+        let mut line = 1;
+        let mut col = 1;
+        for c in self.src.chars().take(self.start) {
+            if c == '\n' {
+                line += 1;
+                col = 1;
+            } else {
+                col += 1;
+            }
+        }
+        (line, col)
+    }
+
+    // the offending line with a caret underline below it
+    pub fn render(&self) -> String {
+        let (line, col) = self.line_col();
+        let text = self.src.lines().nth(line - 1).unwrap_or("");
+
+        // keep tabs so that the caret lines up
+        let pad: String = text
+            .chars()
+            .take(col - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let remaining = text.chars().count().saturating_sub(col - 1);
+        let len = (self.end - self.start).min(remaining).max(1);
+        format!("    {text}\n    {pad}{}", "^".repeat(len))
+    }
+}
+
+impl Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (line, col) = self.line_col();
+        if self.src.contains('\n') {
+            write!(f, "Syntax error at line {line}, column {col}: ")?;
+        } else {
+            write!(f, "Syntax error at column {col}: ")?;
+        }
+        write!(f, "{}\n{}", self.expected, self.render())
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum Error {
-    #[error("Syntax error")]
-    BadSyntax(usize),
+    #[error("{0}")]
+    BadSyntax(SyntaxError),
+    #[error("Empty expression")]
+    EmptyExpr,
     #[error("EndOfFile")]
     EndOfFile,
-    #[error("IO error")]
-    Io,
+    #[error(
+        "offset {0:#x} is no longer buffered, only a memory mapped file can look back that far"
+    )]
+    Unbuffered(usize),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
     #[error("Unknown error")]
     Unknown,
 
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let err = SyntaxError::new("31 \"ab", 3, 6, "unterminated string");
+        assert_eq!(
+            "Syntax error at column 4: unterminated string\n    31 \"ab\n       ^^^",
+            err.to_string()
+        );
+
+        let err = SyntaxError::new("30\n\t31*2", 7, 8, "expected ';' after multiplier");
+        assert_eq!((2, 5), err.line_col());
+        assert_eq!("    \t31*2\n    \t   ^", err.render());
+    }
+}
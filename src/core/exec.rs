@@ -0,0 +1,505 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Mutex,
+    },
+    thread,
+};
+
+use anyhow::{anyhow, Context};
+
+use crate::core::{
+    config::Config,
+    error::Error,
+    input::MmapInput,
+    options::{OutputOptions, SearchOptions},
+    set::MatcherSet,
+};
+
+// result of a search over all inputs
+#[derive(Debug, Default, Clone)]
+pub struct Summary {
+    // amount of matches in all files
+    pub matches: usize,
+
+    // amount of files that could not be searched
+    pub errors: usize,
+}
+
+impl Summary {
+    // grep compatible exit code
+    // 0 if anything matched, 1 if nothing matched and 2 if an error occurred.
+    // When quiet a match always wins over errors.
+    pub fn exit_code(&self, quiet: bool) -> i32 {
+        if self.matches > 0 && (quiet || self.errors == 0) {
+            0
+        } else if self.errors > 0 {
+            2
+        } else {
+            1
+        }
+    }
+}
+
+pub fn exec(cfg: &Config) -> anyhow::Result<Summary> {
+    exec_with(cfg, &mut std::io::stdout().lock())
+}
+
+// search like exec but write the results to o
+pub fn exec_with(cfg: &Config, o: &mut dyn Write) -> anyhow::Result<Summary> {
+    // the trees to apply
+    let matcher = MatcherSet::new(&cfg.patterns()?)?;
+
+    if cfg.dbg_expr_tree {
+        for m in matcher.matchers() {
+            for x in m.tree() {
+                writeln!(o, "{x}")?;
+            }
+        }
+    }
+
+    let search = cfg.search_options();
+    let output = cfg.output_options();
+
+    // either use stdin, or match every file in the file list
+    // a recursive search without paths starts in the working directory
+    let paths = cfg.paths();
+    if paths.is_empty() && !cfg.recursive {
+        let matches = matcher.apply(&mut std::io::stdin().lock(), o, "stdin", &search, &output)?;
+        return Ok(Summary { matches, errors: 0 });
+    }
+
+    let paths = if paths.is_empty() {
+        vec![PathBuf::from(".")]
+    } else {
+        paths
+    };
+
+    let threads = cfg.threads();
+    if threads <= 1 || (paths.len() == 1 && !cfg.recursive) {
+        let mut summary = Summary::default();
+        cfg.walk_options().for_each_file(&paths, &mut |path| {
+            let res = path.and_then(|path| search_file(cfg, &matcher, &path, o, &search, &output));
+            record(cfg, &mut summary, res)
+        })?;
+        Ok(summary)
+    } else {
+        search_parallel(cfg, &matcher, &paths, threads, o, &search, &output)
+    }
+}
+
+type Job = (usize, anyhow::Result<PathBuf>);
+type Block = (usize, anyhow::Result<(Vec<u8>, usize)>);
+
+// Search files on a pool of worker threads.
+// Every file is written to its own buffer first so that the output
+// of one file is never interleaved with another.
+fn search_parallel(
+    cfg: &Config,
+    matcher: &MatcherSet,
+    paths: &[PathBuf],
+    threads: usize,
+    o: &mut dyn Write,
+    search: &SearchOptions,
+    output: &OutputOptions,
+) -> anyhow::Result<Summary> {
+    let (job_tx, job_rx) = mpsc::sync_channel::<Job>(threads * 4);
+    let job_rx = Mutex::new(job_rx);
+    let (block_tx, block_rx) = mpsc::channel::<Block>();
+    let stop = AtomicBool::new(false);
+
+    thread::scope(|s| {
+        for _ in 0..threads {
+            let block_tx = block_tx.clone();
+            let (job_rx, stop) = (&job_rx, &stop);
+            s.spawn(move || {
+                while let Some((index, path)) = next_job(job_rx) {
+                    // keep draining the queue once we are told to stop
+                    // so that the walker never blocks
+                    if stop.load(Ordering::Relaxed) {
+                        continue;
+                    }
+
+                    let mut block = vec![];
+                    let res = path
+                        .and_then(|path| {
+                            search_file(cfg, matcher, &path, &mut block, search, output)
+                        })
+                        .map(|matches| (block, matches));
+                    // the printer only goes away after an error
+                    // in which case stop is set right away
+                    block_tx.send((index, res)).ok();
+                }
+            });
+        }
+        drop(block_tx);
+
+        let stop = &stop;
+        let walker = s.spawn(move || {
+            let mut index = 0;
+            cfg.walk_options().for_each_file(paths, &mut |path| {
+                if stop.load(Ordering::Relaxed) {
+                    return Ok(false);
+                }
+                job_tx.send((index, path))?;
+                index += 1;
+                Ok(true)
+            })
+        });
+
+        let res = print_blocks(cfg, o, block_rx, stop);
+        // the walker may still be waiting for a job to finish
+        stop.store(true, Ordering::Relaxed);
+        let summary = res?;
+
+        walker
+            .join()
+            .unwrap_or_else(|_| Err(anyhow!("walker thread panicked")))?;
+        Ok(summary)
+    })
+}
+
+// take the next job from the shared queue
+fn next_job(jobs: &Mutex<mpsc::Receiver<Job>>) -> Option<Job> {
+    jobs.lock().ok()?.recv().ok()
+}
+
+// write every finished block to o
+// unless unordered blocks are held back until all previous files are done
+fn print_blocks(
+    cfg: &Config,
+    o: &mut dyn Write,
+    blocks: mpsc::Receiver<Block>,
+    stop: &AtomicBool,
+) -> anyhow::Result<Summary> {
+    let mut summary = Summary::default();
+    let mut pending = BTreeMap::new();
+    let mut next = 0;
+
+    let mut print = |block: anyhow::Result<(Vec<u8>, usize)>| {
+        let res = block.and_then(|(block, matches)| {
+            o.write_all(&block)?;
+            Ok(matches)
+        });
+        let more = record(cfg, &mut summary, res)?;
+        if !more {
+            stop.store(true, Ordering::Relaxed);
+        }
+        Ok::<_, anyhow::Error>(more)
+    };
+
+    for (index, block) in blocks {
+        if cfg.unordered {
+            if !print(block)? {
+                break;
+            }
+            continue;
+        }
+
+        pending.insert(index, block);
+        while let Some(block) = pending.remove(&next) {
+            if !print(block)? {
+                return Ok(summary);
+            }
+            next += 1;
+        }
+    }
+    Ok(summary)
+}
+
+// find the io error that caused a failure
+fn io_kind(err: &anyhow::Error) -> Option<ErrorKind> {
+    err.chain().find_map(|e| match e.downcast_ref::<Error>() {
+        Some(Error::Io(e)) => Some(e.kind()),
+        _ => e.downcast_ref::<std::io::Error>().map(|e| e.kind()),
+    })
+}
+
+// Add the result of a single file to the summary.
+// A file that could not be searched is reported to stderr and
+// the search goes on with the next file, unless the output itself went away.
+// Returns false once there is no point in searching any more files.
+fn record(cfg: &Config, summary: &mut Summary, res: anyhow::Result<usize>) -> anyhow::Result<bool> {
+    match res {
+        Ok(matches) => summary.matches += matches,
+        Err(err) => {
+            let kind = io_kind(&err);
+            if kind == Some(ErrorKind::BrokenPipe) {
+                return Err(err);
+            }
+
+            summary.errors += 1;
+            if !cfg.no_messages {
+                match kind {
+                    Some(kind) => eprintln!("rbrep: {err:#} ({kind:?})"),
+                    None => eprintln!("rbrep: {err:#}"),
+                }
+            }
+        }
+    }
+
+    // quiet is done after the first match
+    Ok(!cfg.quiet || summary.matches == 0)
+}
+
+// search a single file
+// errors are tagged with the path of the file
+fn search_file(
+    cfg: &Config,
+    matcher: &MatcherSet,
+    path: &Path,
+    o: &mut dyn Write,
+    search: &SearchOptions,
+    output: &OutputOptions,
+) -> anyhow::Result<usize> {
+    apply_file(cfg, matcher, path, o, search, output).with_context(|| path.display().to_string())
+}
+
+// open a file and apply the matcher to it
+fn apply_file(
+    cfg: &Config,
+    matcher: &MatcherSet,
+    path: &Path,
+    o: &mut dyn Write,
+    search: &SearchOptions,
+    output: &OutputOptions,
+) -> anyhow::Result<usize> {
+    let mut f = File::open(path)?;
+    let name = path.to_str().unwrap_or("");
+
+    if f.metadata()?.is_dir() {
+        return Err(std::io::Error::from(ErrorKind::IsADirectory).into());
+    }
+
+    match mmap(cfg, &f)? {
+        Some(mut input) => matcher.apply_input(&mut input, o, name, search, output),
+        None => matcher.apply(&mut f, o, name, search, output),
+    }
+}
+
+// a memory map of the file unless it has to be streamed
+// regular files can be mapped, everything else
+// (pipes, character devices...) has to be streamed
+fn mmap(cfg: &Config, f: &File) -> anyhow::Result<Option<MmapInput>> {
+    if cfg.no_mmap || !f.metadata()?.is_file() {
+        return Ok(None);
+    }
+    Ok(MmapInput::new(f).ok())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::set::Pattern;
+
+    // a file in the temp directory that is removed again once dropped
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &[u8]) -> Self {
+            let path = std::env::temp_dir().join(format!("rbrep-{}-{name}", std::process::id()));
+            std::fs::write(&path, contents).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            std::fs::remove_file(&self.0).ok();
+        }
+    }
+
+    #[test]
+    fn file_errors() {
+        let cfg = Config::default();
+        let matcher = MatcherSet::new(&[Pattern::new("30", "30")]).unwrap();
+        let search = |path: &str| {
+            search_file(
+                &cfg,
+                &matcher,
+                Path::new(path),
+                &mut vec![],
+                &SearchOptions::default(),
+                &OutputOptions::default(),
+            )
+            .unwrap_err()
+        };
+
+        let err = search("/nonexistent/rbrep");
+        assert_eq!(Some(ErrorKind::NotFound), io_kind(&err));
+        assert!(format!("{err:#}").starts_with("/nonexistent/rbrep: "));
+
+        let err = search("/");
+        assert_eq!(Some(ErrorKind::IsADirectory), io_kind(&err));
+    }
+
+    #[test]
+    fn exit_code() {
+        let summary = |matches, errors| Summary { matches, errors };
+        assert_eq!(0, summary(1, 0).exit_code(false));
+        assert_eq!(1, summary(0, 0).exit_code(false));
+        assert_eq!(2, summary(0, 1).exit_code(false));
+        assert_eq!(2, summary(1, 1).exit_code(false));
+        assert_eq!(0, summary(1, 1).exit_code(true));
+        assert_eq!(2, summary(0, 1).exit_code(true));
+    }
+
+    #[test]
+    fn mmap_or_stream() {
+        let data: Vec<u8> = (0..4096).map(|i| (i * 7 % 256) as u8).collect();
+        let file = TempFile::new("mmap", &data);
+        let open = || File::open(&file.0).unwrap();
+
+        let cfg = Config::default();
+        let no_mmap = Config {
+            no_mmap: true,
+            ..Default::default()
+        };
+        assert!(mmap(&cfg, &open()).unwrap().is_some());
+        assert!(mmap(&no_mmap, &open()).unwrap().is_none());
+        assert!(mmap(&cfg, &File::open("/dev/null").unwrap())
+            .unwrap()
+            .is_none());
+
+        // every way of reading the file finds the same matches
+        let matcher = MatcherSet::new(&[Pattern::new("0e ?? 1c", "0e ?? 1c")]).unwrap();
+        let (search, output) = (SearchOptions::default(), OutputOptions::default());
+        let name = file.0.to_str().unwrap();
+        let mut stdin = vec![];
+        matcher
+            .apply(&mut data.as_slice(), &mut stdin, name, &search, &output)
+            .unwrap();
+        for cfg in [cfg, no_mmap] {
+            let mut out = vec![];
+            let matches = search_file(&cfg, &matcher, &file.0, &mut out, &search, &output);
+            assert_eq!(16, matches.unwrap());
+            assert_eq!(
+                String::from_utf8_lossy(&stdin),
+                String::from_utf8_lossy(&out)
+            );
+        }
+    }
+
+    #[test]
+    fn count() {
+        let a = TempFile::new("count-a", b"abc");
+        let b = TempFile::new("count-b", b"xyz");
+        let cfg = Config {
+            expr: Some("\"abc\"".into()),
+            paths: vec![a.0.clone(), b.0.clone()],
+            count: true,
+            threads: 1,
+            ..Default::default()
+        };
+
+        // a file without a match still names its count
+        let mut out = vec![];
+        let summary = exec_with(&cfg, &mut out).unwrap();
+        assert_eq!(1, summary.matches);
+        assert_eq!(
+            format!("{}\n1\n{}\n0\n", a.0.display(), b.0.display()),
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn look_back() {
+        let mut data = b"X".to_vec();
+        data.resize(256 * 1024, 0);
+        data.extend(b"P\0\0\0\0");
+        let file = TempFile::new("look-back", &data);
+
+        let expr = "\"P\" {p as u32le: ??*4;} @at(p, \"X\")";
+        let matcher = MatcherSet::new(&[Pattern::new(expr, expr)]).unwrap();
+        let search = |cfg: &Config| {
+            search_file(
+                cfg,
+                &matcher,
+                &file.0,
+                &mut vec![],
+                &SearchOptions::default(),
+                &OutputOptions::default(),
+            )
+        };
+
+        // a mapped file can look back all the way, a stream only as far as it buffers
+        assert_eq!(1, search(&Config::default()).unwrap());
+        let streamed = search(&Config {
+            no_mmap: true,
+            ..Default::default()
+        });
+        assert_eq!(0, streamed.unwrap());
+    }
+
+    // search files on 4 threads and split the output into the blocks of each file
+    fn search_threaded(cfg: Config) -> (Summary, Vec<(String, usize)>) {
+        let cfg = Config {
+            expr: Some("61".into()),
+            threads: 4,
+            ..cfg
+        };
+        let mut out = vec![];
+        let summary = exec_with(&cfg, &mut out).unwrap();
+
+        let mut blocks: Vec<(String, usize)> = vec![];
+        for line in String::from_utf8(out).unwrap().lines() {
+            match blocks.last_mut() {
+                Some((_, lines)) if line.contains('\t') => *lines += 1,
+                _ => blocks.push((line.to_string(), 0)),
+            }
+        }
+        (summary, blocks)
+    }
+
+    #[test]
+    fn parallel() {
+        // the first file takes the longest so it is done last
+        let files: Vec<_> = (0..8)
+            .map(|i| {
+                let len = if i == 0 { 1 << 20 } else { 16 * i };
+                TempFile::new(&format!("parallel-{i}"), &vec![b'a'; len])
+            })
+            .collect();
+        let paths: Vec<_> = files.iter().map(|f| f.0.clone()).collect();
+        let expected: Vec<_> = paths
+            .iter()
+            .enumerate()
+            .map(|(i, path)| {
+                let lines = if i == 0 { 1 << 20 } else { 16 * i };
+                (path.display().to_string(), lines)
+            })
+            .collect();
+
+        // every file is a single block, in the order of the arguments
+        let (summary, blocks) = search_threaded(Config {
+            paths: paths.clone(),
+            ..Default::default()
+        });
+        assert_eq!(expected.iter().map(|x| x.1).sum::<usize>(), summary.matches);
+        assert_eq!(expected, blocks);
+
+        // unordered blocks can come in any order but are never interleaved
+        let (_, mut blocks) = search_threaded(Config {
+            paths: paths.clone(),
+            unordered: true,
+            ..Default::default()
+        });
+        blocks.sort();
+        let mut sorted = expected.clone();
+        sorted.sort();
+        assert_eq!(sorted, blocks);
+
+        // quiet stops all threads after the first match
+        let (summary, blocks) = search_threaded(Config {
+            paths,
+            quiet: true,
+            ..Default::default()
+        });
+        assert_eq!(1, summary.matches);
+        assert!(blocks.is_empty());
+    }
+}
@@ -1,70 +1,125 @@
-use console::style;
-use std::{
-    fmt::Display,
-    fs::File,
-    io::{BufReader, Read, Write},
-};
+use std::{collections::HashMap, fmt::Display};
 
 use crate::core::{
-    config::CFG,
-    error::Error,
     error::RbrepResult,
-    input::{FileBufferInput, MatchInput},
-    output::{ExprOutData, ExprOutput, MatchOutput},
+    int::{Endian, IntType},
+    output::CaptureFormat,
     parser::Parser,
 };
 
 pub type ExprBranch = Vec<Expr>;
 
-pub fn exec() -> anyhow::Result<()> {
-    let expr = CFG.expr.clone();
+// how many instructions a pattern may compile to
+// repetitions are unrolled, so a few characters can ask for a lot
+const MAX_SIZE: usize = 1 << 20;
 
-    if CFG.dbg_expr_tree {
-        // the tree to apply
-        let expr = Expr::tree_from(&CFG.expr)?;
-        expr.iter().for_each(|x| println!("{x}"));
-    }
+// how the characters of a string are turned into bytes
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Utf8,
+    Utf16(Endian),
+    Utf32(Endian),
+}
 
-    // either use stdin, or match every file in the file list
-    // TODO allow recursion for directories
-    if !CFG.paths.is_empty() {
-        // open each file and apply parsed tree
-        for path in &CFG.paths {
-            let f = File::open(path)?;
-            Expr::apply(
-                &expr,
-                &mut BufReader::new(f),
-                &mut std::io::stdout(),
-                path.to_str().unwrap_or(""),
-            )?
-        }
-        Ok(())
-    } else {
-        Expr::apply(
-            &expr,
-            &mut BufReader::new(std::io::stdin()),
-            &mut std::io::stdout(),
-            "stdin",
-        )
-    }
+// a part of a string literal
+enum StrUnit {
+    Char(char),
+    // an escaped byte is never encoded
+    Byte(u8),
 }
 
 #[derive(Clone)]
 pub enum ExprKind {
     // a single byte value
-    Byte { value: u8 },
+    Byte {
+        value: u8,
+    },
     // And expression
-    And { value: u8 },
+    And {
+        value: u8,
+    },
     // Not
-    Not { expr: Box<Expr> },
+    Not {
+        expr: Box<Expr>,
+    },
     // any string
     Any,
     // OR combination of expressions
-    Group { nodes: Vec<Expr>, and: bool },
+    Group {
+        nodes: Vec<Expr>,
+        and: bool,
+    },
     // a full string
-    String { value: String },
-    // a range from..to
-    Range { from: u8, to: u8 },
+    String {
+        value: Vec<u8>,
+    },
+    // a byte that matches value after applying mask
+    Mask {
+        mask: u8,
+        value: u8,
+    },
+    // a range from..to, the byte is masked before comparing
+    Range {
+        from: u8,
+        to: u8,
+        mask: u8,
+    },
+    // a multi byte integer
+    Int {
+        ty: IntType,
+        value: i128,
+    },
+    // a range from..=to of multi byte integers, both ends are included
+    IntRange {
+        ty: IntType,
+        from: i128,
+        to: i128,
+    },
+    // a named part of a match that is reported on its own
+    Capture {
+        name: String,
+        format: CaptureFormat,
+        nodes: Vec<Expr>,
+        // the position of the name for errors
+        start: usize,
+        end: usize,
+    },
+    // an integer followed by as many bytes as it says
+    // the bytes have to match nodes if there are any
+    Len {
+        ty: IntType,
+        nodes: Option<Vec<Expr>>,
+    },
+    // nodes have to match at the offset a capture holds
+    // the offset is either absolute or relative to the start of the match
+    At {
+        name: String,
+        relative: bool,
+        nodes: Vec<Expr>,
+        start: usize,
+        end: usize,
+    },
+    // the start of the input
+    Start,
+    // the end of the input
+    End,
+    // an offset that is a multiple of align
+    Align {
+        align: usize,
+    },
+    // the bytes a capture matched
+    Backref {
+        name: String,
+        start: usize,
+        end: usize,
+    },
+    // a reference to a definition
+    // only exists until the parser replaced it with the definition
+    Ref {
+        name: String,
+        start: usize,
+        end: usize,
+    },
 }
 
 impl Display for ExprKind {
@@ -82,8 +137,53 @@ impl Display for ExprKind {
                 }
                 write!(f, "")
             }
-            ExprKind::String { value } => write!(f, "[STRING] value: {value}]"),
-            ExprKind::Range { from, to } => write!(f, "[RANGE] from: {from}, to: {to}]"),
+            ExprKind::String { value } => {
+                write!(f, "[STRING] value: {}]", value.escape_ascii())
+            }
+            ExprKind::Mask { mask, value } => write!(f, "[MASK] mask: {mask}, value: {value}"),
+            ExprKind::Range { from, to, mask } => {
+                write!(f, "[RANGE] from: {from}, to: {to}, mask: {mask}]")
+            }
+            ExprKind::Int { ty, value } => write!(f, "[INT] {ty}: {value}"),
+            ExprKind::IntRange { ty, from, to } => {
+                write!(f, "[INT RANGE] {ty}: from: {from}, to: {to}")
+            }
+            ExprKind::Capture {
+                name,
+                format,
+                nodes,
+                ..
+            } => {
+                write!(f, "[CAPTURE] {name} as {format:?}")?;
+                for node in nodes {
+                    writeln!(f, "{node},")?;
+                }
+                write!(f, "")
+            }
+            ExprKind::Len { ty, nodes } => {
+                write!(f, "[LEN] {ty}")?;
+                for node in nodes.iter().flatten() {
+                    writeln!(f, "{node},")?;
+                }
+                write!(f, "")
+            }
+            ExprKind::At {
+                name,
+                relative,
+                nodes,
+                ..
+            } => {
+                write!(f, "[AT] {name} relative: {relative}")?;
+                for node in nodes {
+                    writeln!(f, "{node},")?;
+                }
+                write!(f, "")
+            }
+            ExprKind::Start => write!(f, "[START]"),
+            ExprKind::End => write!(f, "[END]"),
+            ExprKind::Align { align } => write!(f, "[ALIGN] {align}"),
+            ExprKind::Backref { name, .. } => write!(f, "[BACKREF] \\{name}"),
+            ExprKind::Ref { name, .. } => write!(f, "[REF] ${name}"),
         }?;
         write!(f, "]")
     }
@@ -99,7 +199,15 @@ impl ExprKind {
                     nodes.iter().fold(0, |i, n| i.max(n.kind.len()))
                 }
             }
-            ExprKind::String { value } => value.bytes().len(),
+            ExprKind::Capture { nodes, .. } => nodes.iter().fold(0, |i, n| i + n.kind.len()),
+            ExprKind::String { value } => value.len(),
+            ExprKind::Int { ty, .. } | ExprKind::IntRange { ty, .. } => ty.size,
+            ExprKind::Len { ty, .. } => ty.size,
+            ExprKind::At { .. }
+            | ExprKind::Start
+            | ExprKind::End
+            | ExprKind::Align { .. }
+            | ExprKind::Backref { .. } => 0,
             _ => Expr::single_len(),
         }
     }
@@ -108,93 +216,92 @@ impl ExprKind {
         self.len() == 0
     }
 
-    // An empty vec means no match was found (same as None right now)
-    fn apply_match<IF, OF>(&self, read: &mut IF, res: &mut OF) -> RbrepResult<usize>
-    where
-        IF: MatchInput,
-        OF: MatchOutput,
-    {
-        let offset = res.len();
-        let first = read.read(offset)?;
+    pub fn min_len(&self) -> usize {
         match self {
-            ExprKind::Byte { value } => {
-                if first == *value {
-                    res.push(ExprOutData::new(first, true));
-                }
-            }
-            ExprKind::And { value } => {
-                if first & value != 0 {
-                    res.push(ExprOutData::new(first, true));
-                }
-            }
-            ExprKind::Not { expr } => {
-                // apply matcher to next function, but do not use the
-                // callback. Only if the parser returns an error, call callback
-                // for the next value
-                if expr.apply_match(read, &mut res.clone())? == 0 {
-                    res.push(ExprOutData::new(first, true));
-                }
-            }
-            ExprKind::Any => {
-                res.push(ExprOutData::new(first, false));
-            }
             ExprKind::Group { nodes, and } => {
                 if *and {
-                    let matched = Expr::match_all(nodes, read, res)?;
-                    if matched == 0 {
-                        return Ok(0);
-                    }
+                    Expr::min_len_all(nodes)
                 } else {
-                    Expr::match_any(nodes, read, res)?;
-                }
-            }
-            ExprKind::String { value } => {
-                // compare to literal string
-                for (idx, b) in value.as_bytes().iter().enumerate() {
-                    if read.read(offset + idx)? != *b {
-                        break;
-                    }
-                    res.push(ExprOutData::new(*b, true));
+                    nodes.iter().map(|n| n.min_len()).min().unwrap_or(0)
                 }
             }
-            ExprKind::Range { from, to } => {
-                if (*from..*to).contains(&first) {
-                    res.push(ExprOutData::new(first, true));
+            ExprKind::Capture { nodes, .. } => Expr::min_len_all(nodes),
+            _ => self.len(),
+        }
+    }
+
+    pub fn max_len(&self) -> Option<usize> {
+        match self {
+            ExprKind::Group { nodes, and } => {
+                if *and {
+                    Expr::max_len_all(nodes)
+                } else {
+                    nodes
+                        .iter()
+                        .try_fold(0, |i, n| n.max_len().map(|len| i.max(len)))
                 }
             }
+            ExprKind::Capture { nodes, .. } => Expr::max_len_all(nodes),
+            // a capture or length may be anything
+            ExprKind::Len { .. } | ExprKind::Backref { .. } => None,
+            _ => Some(self.len()),
         }
-        Ok(res.len() - offset)
     }
 }
 
 #[derive(Clone)]
 pub struct Expr {
     kind: ExprKind,
-    mul: u32,
 
-    // match until no more matches are found
-    many: bool,
+    // the expression has to match at least min times
+    min: u32,
 
-    // this will not cause a failure, even if it does not match
-    optional: bool,
+    // and at most max times, None if there is no upper bound
+    max: Option<u32>,
+
+    // repeat as few times as possible instead of as many
+    lazy: bool,
 }
 
 impl Display for Expr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "[kind: {} mul: {}]", self.kind, self.mul)
+        match self.max {
+            Some(max) => write!(f, "[kind: {} min: {} max: {max}", self.kind, self.min),
+            None => write!(f, "[kind: {} min: {} max: inf", self.kind, self.min),
+        }?;
+        if self.lazy {
+            write!(f, " lazy")?;
+        }
+        write!(f, "]")
     }
 }
 
 impl Expr {
-    pub fn new(kind: ExprKind, mul: u32) -> Self {
+    pub fn new(kind: ExprKind) -> Self {
         Self {
             kind,
-            mul,
-            many: false,
-            optional: false,
+            min: 1,
+            max: Some(1),
+            lazy: false,
         }
     }
 
+    pub fn kind(&self) -> &ExprKind {
+        &self.kind
+    }
+
+    pub fn min(&self) -> u32 {
+        self.min
+    }
+
+    pub fn max(&self) -> Option<u32> {
+        self.max
+    }
+
+    pub fn lazy(&self) -> bool {
+        self.lazy
+    }
+
     pub fn tree_from(src: &str) -> RbrepResult<ExprBranch> {
         let mut parser = Parser::new(src);
         Self::tree_from_parser(&mut parser)
@@ -202,12 +309,270 @@ impl Expr {
 
     fn tree_from_parser(parser: &mut Parser) -> RbrepResult<ExprBranch> {
         let mut branch: ExprBranch = vec![];
-        while !parser.is_end() {
+        let mut defs = HashMap::new();
+        // start of the last definition
+        let mut last_def = None;
+        loop {
+            // trailing whitespace is not an expression
+            parser.trim();
+            if parser.is_end() {
+                break;
+            }
+
+            if let Some((name, start)) = Self::parse_definition_name(parser) {
+                if defs.contains_key(&name) {
+                    return Err(parser.error_span(
+                        start,
+                        start + name.len() + 1,
+                        &format!("${name} is defined twice"),
+                    ));
+                }
+                let body = Self::parse_definition(parser, start)?;
+                defs.insert(name, body);
+                last_def = Some(start);
+                continue;
+            }
             branch.push(Self::parse(parser)?);
         }
+
+        // everything after the last definition became part of it
+        if let (true, Some(start)) = (branch.is_empty(), last_def) {
+            return Err(parser.error_span(
+                start,
+                parser.pos,
+                "expected an expression after the definition, \
+                 a definition ends at the end of its line or at a ';' after a '*n;'",
+            ));
+        }
+
+        Self::resolve_all(parser, &mut branch, &defs, &mut vec![])?;
+        if Self::size_all(&branch) > MAX_SIZE {
+            return Err(parser.error_span(0, parser.pos, "pattern is too large"));
+        }
+
+        let mut captures = vec![];
+        Self::captures(&branch, &mut captures);
+        Self::check_captures(parser, &branch, &captures, &mut vec![])?;
         Ok(branch)
     }
 
+    // $name = if a definition starts here
+    // returns the name and the position of the $
+    fn parse_definition_name(parser: &mut Parser) -> Option<(String, usize)> {
+        let start = parser.pos;
+        if parser.adv_if('$') {
+            let name = Self::parse_name(parser);
+            if !name.is_empty() && parser.adv_if_trim('=') {
+                return Some((name, start));
+            }
+        }
+        parser.pos = start;
+        None
+    }
+
+    fn parse_name(parser: &mut Parser) -> String {
+        parser.until(|x| x.is_alphanumeric() || x == '_')
+    }
+
+    // a definition ends at the end of the line or at a ;
+    // the ; of a *n; is part of the repetition and does not end it
+    fn parse_definition(parser: &mut Parser, start: usize) -> RbrepResult<ExprBranch> {
+        let mut body = vec![];
+        loop {
+            parser.trim();
+            if parser.is_end() || parser.newline() || parser.adv_if(';') {
+                break;
+            }
+            body.push(Self::parse(parser)?);
+        }
+
+        if body.is_empty() {
+            return Err(parser.error_span(start, start + 1, "expected an expression after '='"));
+        }
+        Ok(body)
+    }
+
+    // $name
+    fn parse_ref(parser: &mut Parser) -> RbrepResult<Expr> {
+        parser.trim();
+        let start = parser.pos;
+        if !parser.adv_if('$') {
+            return Err(parser.error(start, "expected '$'"));
+        }
+
+        let name = Self::parse_name(parser);
+        if name.is_empty() {
+            return Err(parser.error(start, "expected a name after '$'"));
+        }
+        Ok(Expr::new(ExprKind::Ref {
+            name,
+            start,
+            end: parser.pos,
+        }))
+    }
+
+    // replace every reference with a copy of its definition
+    // stack holds the definitions that are being expanded right now
+    fn resolve_all(
+        parser: &Parser,
+        branch: &mut ExprBranch,
+        defs: &HashMap<String, ExprBranch>,
+        stack: &mut Vec<String>,
+    ) -> RbrepResult<()> {
+        for expr in branch {
+            Self::resolve(parser, expr, defs, stack)?;
+        }
+        Ok(())
+    }
+
+    fn resolve(
+        parser: &Parser,
+        expr: &mut Expr,
+        defs: &HashMap<String, ExprBranch>,
+        stack: &mut Vec<String>,
+    ) -> RbrepResult<()> {
+        match &mut expr.kind {
+            ExprKind::Ref { name, start, end } => {
+                if stack.contains(name) {
+                    return Err(parser.error_span(
+                        *start,
+                        *end,
+                        &format!("${name} refers to itself"),
+                    ));
+                }
+                let Some(def) = defs.get(name) else {
+                    return Err(parser.error_span(
+                        *start,
+                        *end,
+                        &format!("${name} is not defined"),
+                    ));
+                };
+
+                let mut nodes = def.clone();
+                stack.push(name.clone());
+                Self::resolve_all(parser, &mut nodes, defs, stack)?;
+                stack.pop();
+
+                // definitions that use each other twice double in size every time
+                let (name, start, end) = (name.clone(), *start, *end);
+                expr.kind = ExprKind::Group { nodes, and: true };
+                if expr.size() > MAX_SIZE {
+                    return Err(parser.error_span(
+                        start,
+                        end,
+                        &format!("${name} makes the pattern too large"),
+                    ));
+                }
+            }
+            ExprKind::Group { nodes, .. } => Self::resolve_all(parser, nodes, defs, stack)?,
+            ExprKind::Capture {
+                format,
+                nodes,
+                start,
+                end,
+                ..
+            } => {
+                Self::resolve_all(parser, nodes, defs, stack)?;
+                // the size is only known once every reference is resolved
+                if let CaptureFormat::Int(ty) = format {
+                    if Self::min_len_all(nodes) != ty.size
+                        || Self::max_len_all(nodes) != Some(ty.size)
+                    {
+                        return Err(parser.error_span(
+                            *start,
+                            *end,
+                            &format!("a {ty} capture has to match exactly {} bytes", ty.size),
+                        ));
+                    }
+                }
+            }
+            ExprKind::Len {
+                nodes: Some(nodes), ..
+            }
+            | ExprKind::At { nodes, .. } => Self::resolve_all(parser, nodes, defs, stack)?,
+            ExprKind::Not { expr } => Self::resolve(parser, expr, defs, stack)?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // the expressions that are part of this one
+    pub fn children(&self) -> &[Expr] {
+        match &self.kind {
+            ExprKind::Group { nodes, .. }
+            | ExprKind::Capture { nodes, .. }
+            | ExprKind::Len {
+                nodes: Some(nodes), ..
+            }
+            | ExprKind::At { nodes, .. } => nodes,
+            ExprKind::Not { expr } => std::slice::from_ref(expr.as_ref()),
+            _ => &[],
+        }
+    }
+
+    // name and format of every capture
+    fn captures(branch: &[Expr], captures: &mut Vec<(String, CaptureFormat)>) {
+        for expr in branch {
+            if let ExprKind::Capture { name, format, .. } = &expr.kind {
+                captures.push((name.clone(), *format));
+            }
+            Self::captures(expr.children(), captures);
+        }
+    }
+
+    // every backreference and offset needs a capture that is not one it is part of
+    // stack holds the captures that surround the current expression
+    fn check_captures(
+        parser: &Parser,
+        branch: &[Expr],
+        captures: &[(String, CaptureFormat)],
+        stack: &mut Vec<String>,
+    ) -> RbrepResult<()> {
+        for expr in branch {
+            let (name, label, start, end) = match &expr.kind {
+                ExprKind::Backref { name, start, end } => (name, format!("\\{name}"), *start, *end),
+                ExprKind::At {
+                    name, start, end, ..
+                } => (name, name.clone(), *start, *end),
+                ExprKind::Capture { name, nodes, .. } => {
+                    stack.push(name.clone());
+                    Self::check_captures(parser, nodes, captures, stack)?;
+                    stack.pop();
+                    continue;
+                }
+                _ => {
+                    Self::check_captures(parser, expr.children(), captures, stack)?;
+                    continue;
+                }
+            };
+
+            if stack.contains(name) {
+                return Err(parser.error_span(
+                    start,
+                    end,
+                    &format!("{label} refers to a capture it is part of"),
+                ));
+            }
+            let Some((_, format)) = captures.iter().find(|(n, _)| n == name) else {
+                return Err(parser.error_span(
+                    start,
+                    end,
+                    &format!("there is no capture named {name}"),
+                ));
+            };
+            if matches!(expr.kind, ExprKind::At { .. }) && !matches!(format, CaptureFormat::Int(_))
+            {
+                return Err(parser.error_span(
+                    start,
+                    end,
+                    "an offset has to be an integer capture (e.g. {ptr as u32le: ??*4;})",
+                ));
+            }
+            Self::check_captures(parser, expr.children(), captures, stack)?;
+        }
+        Ok(())
+    }
+
     pub fn single_len() -> usize {
         1
     }
@@ -220,353 +585,754 @@ impl Expr {
         self.len() == 0
     }
 
+    pub fn min_len(&self) -> usize {
+        self.kind.min_len() * self.min as usize
+    }
+
+    // None if the expression may repeat forever
+    pub fn max_len(&self) -> Option<usize> {
+        let max = self.max?;
+        self.kind.max_len().map(|len| len * max as usize)
+    }
+
+    // about how many instructions the expression compiles to
+    pub fn size(&self) -> usize {
+        let body = match &self.kind {
+            ExprKind::String { value } => value.len().max(1),
+            ExprKind::Not { expr } => expr.size().saturating_add(1),
+            _ => {
+                let nodes = self.children();
+                Self::size_all(nodes).saturating_add(2 * nodes.len()).max(1)
+            }
+        };
+        // every repetition is a copy of the body and a split
+        let copies = self.max.unwrap_or(self.min.saturating_add(1)).max(1) as usize;
+        body.saturating_add(1).saturating_mul(copies)
+    }
+
+    pub fn size_all(expr: &[Expr]) -> usize {
+        expr.iter().fold(0, |size, e| size.saturating_add(e.size()))
+    }
+
+    pub fn min_len_all(expr: &ExprBranch) -> usize {
+        expr.iter().map(|e| e.min_len()).sum()
+    }
+
+    pub fn max_len_all(expr: &ExprBranch) -> Option<usize> {
+        expr.iter()
+            .try_fold(0, |i, e| e.max_len().map(|len| i + len))
+    }
+
     fn parse_byte_value(parser: &mut Parser) -> RbrepResult<u8> {
+        parser.trim();
+        let start = parser.pos;
         let first = parser.adv();
         let second = parser.adv();
         u8::from_str_radix(&format!("{first}{second}"), 16)
-            .map_err(|_| Error::BadSyntax(parser.pos))
+            .map_err(|_| parser.error(start, "expected a hex byte (e.g. 1f)"))
     }
 
-    fn parse_byte_or_range(parser: &mut Parser) -> RbrepResult<Expr> {
+    // a byte where either nibble may be a ? wildcard (e.g. 4? or ?f)
+    // or an explicit mask/value pair (e.g. f0/40)
+    // returns (mask, value)
+    fn parse_masked_byte(parser: &mut Parser) -> RbrepResult<(u8, u8)> {
+        parser.trim();
+        let start = parser.pos;
+        let mut mask = 0;
+        let mut value = 0;
+        for _ in 0..2 {
+            let c = parser.adv();
+            mask <<= 4;
+            value <<= 4;
+            if c != '?' {
+                let nibble = c
+                    .to_digit(16)
+                    .ok_or_else(|| parser.error(start, "expected a hex byte (e.g. 1f or 4?)"))?;
+                mask |= 0xF;
+                value |= nibble as u8;
+            }
+        }
+
+        if !parser.adv_if_trim('/') {
+            return Ok((mask, value));
+        }
+
+        if mask != 0xFF {
+            return Err(parser.error(start, "a mask may not contain wildcards"));
+        }
+        let mask = value;
         let value = Self::parse_byte_value(parser)?;
+        if value & !mask != 0 {
+            return Err(parser.error(start, "value has bits outside of the mask"));
+        }
+        Ok((mask, value))
+    }
+
+    fn parse_byte_or_range(parser: &mut Parser) -> RbrepResult<Expr> {
+        parser.trim();
+        let start = parser.pos;
+        let (mask, value) = Self::parse_masked_byte(parser)?;
         if parser.adv_if_trim('-') {
-            let value2 = Self::parse_byte_value(parser)?;
-            Ok(Expr::new(
-                ExprKind::Range {
-                    // TODO should we allow this behaviour?
-                    from: value.min(value2),
-                    to: value2.max(value),
-                },
-                1,
-            ))
+            // the mask of the start also applies to a plain byte at the end
+            let (mask2, value2) = Self::parse_masked_byte(parser)?;
+            if mask != mask2 && (mask2 != 0xFF || value2 & !mask != 0) {
+                return Err(parser.error(start, "both ends of a range need the same mask"));
+            }
+            Ok(Expr::new(ExprKind::Range {
+                // TODO should we allow this behaviour?
+                from: value.min(value2),
+                to: value2.max(value),
+                mask,
+            }))
+        } else if mask == 0 {
+            Ok(Expr::new(ExprKind::Any))
+        } else if mask == 0xFF {
+            Ok(Expr::new(ExprKind::Byte { value }))
         } else {
-            Ok(Expr::new(ExprKind::Byte { value }, 1))
+            Ok(Expr::new(ExprKind::Mask { mask, value }))
         }
     }
 
-    fn parse_any(parser: &mut Parser) -> RbrepResult<Expr> {
-        if parser.adv_if_trim('?') && parser.adv_if_trim('?') {
-            Ok(Expr::new(ExprKind::Any, 1))
+    // a typed integer value e.g. 0x1234 or -5
+    fn parse_int_value(parser: &mut Parser, ty: &IntType) -> RbrepResult<i128> {
+        parser.trim();
+        let start = parser.pos;
+        let neg = parser.adv_if('-');
+        let digits = parser.until(|x| x.is_ascii_alphanumeric() || x == '_');
+        let digits = digits.replace('_', "");
+
+        let value = if let Some(hex) = digits.strip_prefix("0x") {
+            i128::from_str_radix(hex, 16)
         } else {
-            Err(Error::BadSyntax(parser.pos))
+            digits.parse::<i128>()
+        }
+        .map_err(|_| parser.error(start, "expected an integer (e.g. 0x1f or 31)"))?;
+
+        let value = if neg { -value } else { value };
+        if !ty.contains(value) {
+            return Err(parser.error(start, &format!("value does not fit into {ty}")));
         }
+        Ok(value)
     }
 
-    fn parse_mul(parser: &mut Parser, mut expr: Expr) -> RbrepResult<Expr> {
+    // typed integers e.g. u16le:0x1234 or ranges u32be:10-20
+    fn parse_int(parser: &mut Parser) -> RbrepResult<Expr> {
+        parser.trim();
+        let start = parser.pos;
+        let name = parser.until(|x| x.is_ascii_alphanumeric());
+        let ty = IntType::from_name(&name).ok_or_else(|| {
+            parser.error(start, "expected an integer type (e.g. u8, u16le or i32be)")
+        })?;
+
+        if !parser.adv_if_trim(':') {
+            return Err(parser.error(parser.pos, "expected ':' after integer type"));
+        }
+
+        let value = Self::parse_int_value(parser, &ty)?;
+        if parser.adv_if_trim('-') {
+            let value2 = Self::parse_int_value(parser, &ty)?;
+            Ok(Expr::new(ExprKind::IntRange {
+                ty,
+                from: value.min(value2),
+                to: value2.max(value),
+            }))
+        } else {
+            Ok(Expr::new(ExprKind::Int { ty, value }))
+        }
+    }
+
+    // a { after an expression is only a quantifier if a bound follows
+    // otherwise it starts a capture
+    fn is_bounds(parser: &mut Parser) -> bool {
+        if parser.peek_trim() != '{' {
+            return false;
+        }
+        let mut n = 1;
+        while parser.peek_at(n) == ' ' {
+            n += 1;
+        }
+        matches!(parser.peek_at(n), '0'..='9' | ',')
+    }
+
+    fn parse_mul(parser: &mut Parser, expr: Expr) -> RbrepResult<Expr> {
+        parser.trim();
+        let start = parser.pos;
+        let expr = Self::parse_repetition(parser, expr)?;
+        if parser.pos > start && expr.size() > MAX_SIZE {
+            return Err(parser.error(start, "repetition makes the pattern too large"));
+        }
+        Ok(expr)
+    }
+
+    fn parse_repetition(parser: &mut Parser, mut expr: Expr) -> RbrepResult<Expr> {
+        if Self::is_bounds(parser) {
+            let (min, max) = Self::parse_bounds(parser)?;
+            expr.min = min;
+            expr.max = max;
+
+            // a ? right after the bounds makes it lazy
+            // unless it starts a wildcard byte (e.g. 30{2}?? is 30 30 ??)
+            // 30{2}?3 could be either, a wildcard nibble needs a space before it
+            if parser.peek() == '?' {
+                match parser.peek_at(1) {
+                    '?' => {}
+                    '0'..='9' | 'a'..='f' | 'A'..='F' => {
+                        return Err(parser.error_span(
+                            parser.pos,
+                            parser.pos + 2,
+                            "a ? right after a repetition makes it lazy, \
+                             put a space before a wildcard (e.g. 30{2} ?1)",
+                        ));
+                    }
+                    _ => {
+                        parser.adv_raw();
+                        expr.lazy = true;
+                    }
+                }
+            }
+            return Ok(expr);
+        }
+
         // if not a mul return
         if !parser.adv_if_trim('*') {
             return Ok(expr);
         }
 
         // now, get the slice of a numbers
+        parser.trim();
+        let start = parser.pos;
         let num = parser.until(|x| x.is_ascii_digit());
 
         let num = num
             .parse::<u32>()
-            .map_err(|_| Error::BadSyntax(parser.pos))?;
+            .map_err(|_| parser.error(start, "expected a number after '*'"))?;
 
-        if num == 0 {
-            expr.optional = true;
-            expr.mul = 1;
-        } else {
-            expr.mul = num;
-        }
+        // *0; is optional, everything else is an exact amount
+        expr.min = num;
+        expr.max = Some(num.max(1));
 
-        // if + follows mul set many flag
+        // if + follows mul there is no upper bound
         if parser.adv_if_trim('+') {
-            expr.many = true;
+            expr.max = None;
         }
 
+        // a ? before the ; makes it lazy
+        expr.lazy = parser.adv_if_trim('?');
+
         // ; is required after mul
         if !parser.adv_if_trim(';') {
-            Err(Error::BadSyntax(parser.pos))
+            Err(parser.error(parser.pos, "expected ';' after multiplier"))
         } else {
             Ok(expr)
         }
     }
 
+    // {n}, {n,}, {,m} or {n,m}
+    // returns (min, max)
+    fn parse_bounds(parser: &mut Parser) -> RbrepResult<(u32, Option<u32>)> {
+        parser.trim();
+        let start = parser.pos;
+        if !parser.adv_if('{') {
+            return Err(parser.error(start, "expected '{'"));
+        }
+
+        let bound = |parser: &mut Parser| {
+            parser.trim();
+            let from = parser.pos;
+            let num = parser.until(|x| x.is_ascii_digit());
+            if num.is_empty() {
+                return Ok(None);
+            }
+            num.parse::<u32>()
+                .map(Some)
+                .map_err(|_| parser.error(from, "repetition bound is too large"))
+        };
+
+        let min = bound(parser)?;
+        let max = if parser.adv_if_trim(',') {
+            bound(parser)?
+        } else {
+            // {n} is exactly n times
+            Some(min.ok_or_else(|| parser.error(parser.pos, "expected a number after '{'"))?)
+        };
+
+        if !parser.adv_if_trim('}') {
+            return Err(parser.error(parser.pos, "expected '}' after repetition"));
+        }
+
+        let min = min.unwrap_or(0);
+        match max {
+            Some(0) => Err(parser.error(start, "repetition has to allow at least one match")),
+            Some(max) if max < min => {
+                Err(parser.error(start, "repetition minimum exceeds maximum"))
+            }
+            max => Ok((min, max)),
+        }
+    }
+
     fn parse_group(parser: &mut Parser, and: bool) -> RbrepResult<Expr> {
+        parser.trim();
+        let start = parser.pos;
         if !parser.adv_if_trim('(') {
-            return Err(Error::BadSyntax(parser.pos));
+            return Err(parser.error(start, "expected '('"));
         }
 
         let mut nodes = vec![];
 
         while !parser.adv_if_trim(')') {
             if parser.is_end() {
-                return Err(Error::BadSyntax(parser.pos));
+                return Err(parser.error_span(start, start + 1, "unclosed group"));
             }
             nodes.push(Self::parse(parser)?);
         }
 
-        Ok(Expr::new(ExprKind::Group { nodes, and }, 1))
+        Ok(Expr::new(ExprKind::Group { nodes, and }))
     }
 
-    fn parse_string(parser: &mut Parser) -> RbrepResult<Expr> {
-        if !parser.adv_if_trim('"') {
-            return Err(Error::BadSyntax(parser.pos));
+    // {name: ...} or {name as u16le: ...}
+    fn parse_capture(parser: &mut Parser) -> RbrepResult<Expr> {
+        parser.trim();
+        let start = parser.pos;
+        if !parser.adv_if('{') {
+            return Err(parser.error(start, "expected '{'"));
         }
 
-        let start = parser.pos;
-        // FIXME maybe allow escaping "s
-        // but for now the user could just insert the ascii value...
-        while !parser.adv_if_trim('"') {
+        parser.trim();
+        let name = Self::parse_name(parser);
+        if name.is_empty() {
+            return Err(parser.error(start, "expected a capture name (e.g. {name: ...})"));
+        }
+
+        let mut format = CaptureFormat::default();
+        parser.trim();
+        let before_as = parser.pos;
+        if parser.until(|x| x.is_ascii_alphanumeric()) == "as" {
+            parser.trim();
+            let from = parser.pos;
+            let ty = parser.until(|x| x.is_ascii_alphanumeric());
+            format = CaptureFormat::from_name(&ty).ok_or_else(|| {
+                parser.error(
+                    from,
+                    "expected a capture format (hex, str or an integer type e.g. u16le)",
+                )
+            })?;
+        } else {
+            parser.pos = before_as;
+        }
+
+        if !parser.adv_if_trim(':') {
+            return Err(parser.error(parser.pos, "expected ':' after capture name"));
+        }
+        let end = parser.pos;
+
+        let mut nodes = vec![];
+        while !parser.adv_if_trim('}') {
             if parser.is_end() {
-                return Err(Error::BadSyntax(parser.pos));
+                return Err(parser.error_span(start, start + 1, "unclosed capture"));
             }
-            parser.adv();
+            nodes.push(Self::parse(parser)?);
         }
-        let end = parser.pos - 1;
-        let string = parser.src[start..end].to_owned();
-        Ok(Expr::new(ExprKind::String { value: string }, 1))
+        if nodes.is_empty() {
+            return Err(parser.error_span(start, end, "a capture has to contain an expression"));
+        }
+
+        Ok(Expr::new(ExprKind::Capture {
+            name,
+            format,
+            nodes,
+            start,
+            end,
+        }))
     }
 
-    fn parse_and(parser: &mut Parser) -> RbrepResult<Expr> {
-        if !parser.adv_if_trim('&') {
-            return Err(Error::BadSyntax(parser.pos));
+    // @len(u16le), @len(u16le, ...), @at(name, ...), @rel(name, ...) or @align(n)
+    fn parse_directive(parser: &mut Parser) -> RbrepResult<Expr> {
+        parser.trim();
+        let start = parser.pos;
+        if !parser.adv_if('@') {
+            return Err(parser.error(start, "expected '@'"));
         }
 
-        // is it an and group?
-        if parser.peek_trim() == '(' {
-            Self::parse_group(parser, true)
+        let directive = Self::parse_name(parser);
+        if !matches!(directive.as_str(), "len" | "at" | "rel" | "align") {
+            return Err(parser.error(start, "expected @len, @at, @rel or @align"));
+        }
+        if !parser.adv_if_trim('(') {
+            return Err(parser.error(parser.pos, &format!("expected '(' after @{directive}")));
+        }
+
+        parser.trim();
+        let from = parser.pos;
+        let kind = if directive == "align" {
+            let align = parser
+                .until(|x| x.is_ascii_digit())
+                .parse::<usize>()
+                .ok()
+                .filter(|align| *align > 0)
+                .ok_or_else(|| parser.error(from, "expected an alignment greater than 0"))?;
+            if !parser.adv_if_trim(')') {
+                return Err(parser.error(parser.pos, "expected ')' after the alignment"));
+            }
+            ExprKind::Align { align }
+        } else if directive == "len" {
+            let name = parser.until(|x| x.is_ascii_alphanumeric());
+            let ty = IntType::from_name(&name).ok_or_else(|| {
+                parser.error(from, "expected an integer type (e.g. u8, u16le or i32be)")
+            })?;
+
+            let nodes = if parser.adv_if_trim(',') {
+                Some(Self::parse_directive_body(parser, start)?)
+            } else if parser.adv_if_trim(')') {
+                None
+            } else {
+                return Err(parser.error(parser.pos, "expected ',' or ')'"));
+            };
+            ExprKind::Len { ty, nodes }
         } else {
-            let value = Self::parse_byte_value(parser)?;
+            let name = Self::parse_name(parser);
+            if name.is_empty() {
+                return Err(parser.error(from, "expected a capture name"));
+            }
+            let end = parser.pos;
+
+            if !parser.adv_if_trim(',') {
+                return Err(parser.error(parser.pos, "expected ',' after the capture name"));
+            }
+            ExprKind::At {
+                name,
+                relative: directive == "rel",
+                nodes: Self::parse_directive_body(parser, start)?,
+                start: from,
+                end,
+            }
+        };
+        Ok(Expr::new(kind))
+    }
 
-            Ok(Expr::new(ExprKind::And { value }, 1))
+    // the expressions after the , of a directive up to the )
+    fn parse_directive_body(parser: &mut Parser, start: usize) -> RbrepResult<ExprBranch> {
+        let mut nodes = vec![];
+        while !parser.adv_if_trim(')') {
+            if parser.is_end() {
+                return Err(parser.error_span(start, start + 1, "unclosed directive"));
+            }
+            nodes.push(Self::parse(parser)?);
+        }
+        if nodes.is_empty() {
+            return Err(parser.error(parser.pos - 1, "expected an expression before ')'"));
         }
+        Ok(nodes)
     }
 
-    fn parse_not(parser: &mut Parser) -> RbrepResult<Expr> {
-        if !parser.adv_if_trim('!') {
-            return Err(Error::BadSyntax(parser.pos));
+    // \\name
+    fn parse_backref(parser: &mut Parser) -> RbrepResult<Expr> {
+        parser.trim();
+        let start = parser.pos;
+        if !parser.adv_if('\\') {
+            return Err(parser.error(start, "expected '\\'"));
+        }
+
+        let name = Self::parse_name(parser);
+        if name.is_empty() {
+            return Err(parser.error(start, "expected a capture name after '\\'"));
         }
-        Ok(Expr::new(
-            ExprKind::Not {
-                expr: Box::new(Self::parse(parser)?),
-            },
-            1,
-        ))
+        Ok(Expr::new(ExprKind::Backref {
+            name,
+            start,
+            end: parser.pos,
+        }))
     }
 
-    fn parse(parser: &mut Parser) -> RbrepResult<Expr> {
-        let first = parser.peek_trim();
+    fn parse_string(parser: &mut Parser) -> RbrepResult<Expr> {
+        let units = Self::parse_string_units(parser)?;
+        let value = Self::encode(&units, Encoding::Utf8)
+            .into_iter()
+            .map(|(b, _)| b)
+            .collect();
+        Ok(Expr::new(ExprKind::String { value }))
+    }
 
-        let expr = match first {
-            '?' => Self::parse_any(parser),
-            '(' => Self::parse_group(parser, false),
-            '"' => Self::parse_string(parser),
-            '&' => Self::parse_and(parser),
-            '!' => Self::parse_not(parser),
-            _ => {
-                if first.is_ascii_hexdigit() {
-                    Self::parse_byte_or_range(parser)
-                } else {
-                    Err(Error::BadSyntax(parser.pos))
-                }
+    fn parse_string_units(parser: &mut Parser) -> RbrepResult<Vec<StrUnit>> {
+        parser.trim();
+        let start = parser.pos;
+        if !parser.adv_if('"') {
+            return Err(parser.error(start, "expected '\"'"));
+        }
+
+        let mut units = vec![];
+        while !parser.adv_if('"') {
+            if parser.is_end() {
+                return Err(parser.error(start, "unterminated string"));
             }
-        }?;
 
-        Self::parse_mul(parser, expr)
+            let c = parser.adv_raw();
+            if c == '\\' {
+                units.push(StrUnit::Byte(Self::parse_escape(parser, start)?));
+            } else {
+                units.push(StrUnit::Char(c));
+            }
+        }
+        Ok(units)
     }
 
-    fn apply_match<IF, OF>(&self, i: &mut IF, res: &mut OF) -> RbrepResult<usize>
-    where
-        IF: MatchInput,
-        OF: MatchOutput,
-    {
-        let start = res.len();
-        for _ in 0..self.mul {
-            let matched = self.kind.apply_match(i, res)?;
+    // true if modifiers followed by a string start here (e.g. iw"abc")
+    fn is_modified_string(parser: &Parser) -> bool {
+        let mut n = 0;
+        while parser.peek_at(n).is_ascii_alphanumeric() {
+            n += 1;
+        }
+        n > 0 && parser.peek_at(n) == '"'
+    }
 
-            if matched == 0 && !self.optional {
-                return Ok(0);
+    // i"abc" is case insensitive
+    // w"abc" and W"abc" are utf-16 little and big endian
+    // u32"abc" and U32"abc" are utf-32 little and big endian
+    fn parse_modified_string(parser: &mut Parser) -> RbrepResult<Expr> {
+        parser.trim();
+        let start = parser.pos;
+        let name = parser.until(|x| x.is_ascii_alphanumeric());
+
+        let mut nocase = false;
+        let mut encoding = None;
+        let mut rest = name.as_str();
+        while !rest.is_empty() {
+            let (modifier, enc) = [
+                ("i", None),
+                ("w", Some(Encoding::Utf16(Endian::Little))),
+                ("W", Some(Encoding::Utf16(Endian::Big))),
+                ("u32", Some(Encoding::Utf32(Endian::Little))),
+                ("U32", Some(Encoding::Utf32(Endian::Big))),
+            ]
+            .into_iter()
+            .find(|(modifier, _)| rest.starts_with(modifier))
+            .ok_or_else(|| {
+                parser.error(
+                    start,
+                    "unknown string modifier (expected i, w, W, u32 or U32)",
+                )
+            })?;
+            rest = &rest[modifier.len()..];
+
+            match enc {
+                None => nocase = true,
+                Some(_) if encoding.is_some() => {
+                    return Err(parser.error(start, "a string can only have one encoding"));
+                }
+                enc => encoding = enc,
             }
+        }
 
-            // call again if many flag is set and we had a result
-            if (matched != 0 || self.optional) && self.many {
-                loop {
-                    let matched = self.kind.apply_match(i, res)?;
+        let units = Self::parse_string_units(parser)?;
+        let bytes = Self::encode(&units, encoding.unwrap_or(Encoding::Utf8));
+        if !nocase || !bytes.iter().any(|(_, letter)| *letter) {
+            let value = bytes.into_iter().map(|(b, _)| b).collect();
+            return Ok(Expr::new(ExprKind::String { value }));
+        }
 
-                    // no change?
-                    if matched == 0 {
-                        break;
+        // letters match both cases, upper and lower case only differ in bit 5
+        let nodes = bytes
+            .into_iter()
+            .map(|(b, letter)| {
+                if letter {
+                    Expr::new(ExprKind::Mask {
+                        mask: 0xDF,
+                        value: b & 0xDF,
+                    })
+                } else {
+                    Expr::new(ExprKind::Byte { value: b })
+                }
+            })
+            .collect();
+        Ok(Expr::new(ExprKind::Group { nodes, and: true }))
+    }
+
+    // the bytes of a string and if they are an ascii letter
+    fn encode(units: &[StrUnit], encoding: Encoding) -> Vec<(u8, bool)> {
+        let mut bytes = vec![];
+        for unit in units {
+            let (c, letter) = match unit {
+                StrUnit::Char(c) => (*c as u32, c.is_ascii_alphabetic()),
+                StrUnit::Byte(b) => (*b as u32, false),
+            };
+
+            let start = bytes.len();
+            match (encoding, unit) {
+                (Encoding::Utf8, StrUnit::Char(c)) => {
+                    let mut buf = [0; 4];
+                    bytes.extend(c.encode_utf8(&mut buf).bytes().map(|b| (b, false)));
+                }
+                (Encoding::Utf8, StrUnit::Byte(b)) => bytes.push((*b, false)),
+                (Encoding::Utf16(endian), _) => {
+                    let mut buf = [0; 2];
+                    let units = match unit {
+                        StrUnit::Char(c) => c.encode_utf16(&mut buf),
+                        StrUnit::Byte(b) => {
+                            buf[0] = *b as u16;
+                            &mut buf[..1]
+                        }
+                    };
+                    for unit in units {
+                        let unit = match endian {
+                            Endian::Little => unit.to_le_bytes(),
+                            Endian::Big => unit.to_be_bytes(),
+                        };
+                        bytes.extend(unit.map(|b| (b, false)));
                     }
                 }
+                (Encoding::Utf32(endian), _) => {
+                    let unit = match endian {
+                        Endian::Little => c.to_le_bytes(),
+                        Endian::Big => c.to_be_bytes(),
+                    };
+                    bytes.extend(unit.map(|b| (b, false)));
+                }
+            }
+
+            // only the byte that holds the letter may change its case
+            if letter {
+                if let Some(b) = bytes[start..].iter_mut().find(|(b, _)| *b != 0) {
+                    b.1 = true;
+                }
             }
         }
-        Ok(res.len() - start)
+        bytes
     }
 
-    pub fn start_match_from<IF, OF>(expr: &ExprBranch, i: &mut IF) -> RbrepResult<OF>
-    where
-        IF: MatchInput,
-        OF: MatchOutput,
-    {
-        let mut res = OF::default();
-        if Self::match_all(expr, i, &mut res)? == 0 {
-            return Ok(OF::default());
+    // the byte after a \ in a string
+    fn parse_escape(parser: &mut Parser, string_start: usize) -> RbrepResult<u8> {
+        let start = parser.pos - 1;
+        if parser.is_end() {
+            return Err(parser.error(string_start, "unterminated string"));
         }
 
-        Ok(res)
+        let value = match parser.adv_raw() {
+            'n' => b'\n',
+            'r' => b'\r',
+            't' => b'\t',
+            '0' => 0,
+            '\\' => b'\\',
+            '"' => b'"',
+            'x' => {
+                let hex: String = [parser.adv_raw(), parser.adv_raw()].iter().collect();
+                u8::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| hex.chars().all(|c| c.is_ascii_hexdigit()))
+                    .ok_or_else(|| {
+                        parser.error(start, "expected two hex digits after '\\x' (e.g. \\x1f)")
+                    })?
+            }
+            c => {
+                return Err(parser.error(
+                    start,
+                    &format!(
+                    "unknown escape '\\{c}', expected one of \\n \\r \\t \\0 \\\\ \\\" or \\xNN"
+                ),
+                ))
+            }
+        };
+        Ok(value)
     }
 
-    pub fn start_match<IF, OF>(expr: &str, reader: &mut IF) -> RbrepResult<OF>
-    where
-        IF: MatchInput,
-        OF: MatchOutput,
-    {
-        let expr = Expr::tree_from(expr)?;
-        Self::start_match_from(&expr, reader)
-    }
+    fn parse_and(parser: &mut Parser) -> RbrepResult<Expr> {
+        parser.trim();
+        let start = parser.pos;
+        if !parser.adv_if_trim('&') {
+            return Err(parser.error(start, "expected '&'"));
+        }
 
-    pub fn for_each_match<IF, OF, CB>(
-        expr: &str,
-        reader: &mut IF,
-        each: &mut CB,
-    ) -> anyhow::Result<()>
-    where
-        IF: MatchInput,
-        OF: MatchOutput,
-        CB: FnMut(&ExprBranch, &mut IF, &OF) -> anyhow::Result<bool>,
-    {
-        // the tree to apply
-        let expr = Expr::tree_from(expr)?;
-        while !reader.eof() {
-            let res: OF = Self::start_match_from(&expr, reader)?;
-            if !each(&expr, reader, &res)? {
-                break;
-            }
+        // is it an and group?
+        if parser.peek_trim() == '(' {
+            Self::parse_group(parser, true)
+        } else {
+            let value = Self::parse_byte_value(parser)?;
 
-            // FIXME are there cases where advnacing by res.len() is not ok?
-            reader.advance(1)?;
+            Ok(Expr::new(ExprKind::And { value }))
         }
-        Ok(())
     }
 
-    // match all
-    // the buffer should match the lenght of the expr
-    // the i callback gets called with the byte offset the matcher is attempting to read
-    // i should return None if the read failed
-    // and a byte value if the read was ok
-    // i can be implemented in any way required to provide data to the matcher
-    fn match_all<IF, OF>(expr: &ExprBranch, i: &mut IF, res: &mut OF) -> RbrepResult<usize>
-    where
-        IF: MatchInput,
-        OF: MatchOutput,
-    {
-        let start = res.len();
-        for e in expr {
-            let matched = e.apply_match(i, res)?;
-            // no match => return empty
-            if matched == 0 && !e.optional {
-                return Ok(0);
-            }
-        }
-        // got to end without fail => match found!
-        Ok(res.len() - start)
-    }
-
-    // match any
-    // matches any of the group and if it ends up matching, returns
-    fn match_any<IF, OF>(expr: &ExprBranch, i: &mut IF, res: &mut OF) -> RbrepResult<usize>
-    where
-        IF: MatchInput,
-        OF: MatchOutput,
-    {
-        let start = res.len();
-        for e in expr {
-            let matched = e.apply_match(i, res)?;
-            if matched != 0 {
-                return Ok(res.len() - start);
-            }
-        }
-        // got to end without success => no match found!
-        Ok(0)
-    }
-
-    // here we read the data and manage the buffer
-    pub fn apply(
-        expr: &str,
-        i: &mut dyn Read,
-        o: &mut dyn Write,
-        name: &str,
-    ) -> anyhow::Result<()> {
-        let mut input = FileBufferInput::new(i);
-
-        let mut first_in_file = true;
-        let mut matches = 0;
-
-        // no matter what, we always advance a single byte
-        // to check all possible combinations
-        Self::for_each_match(
-            expr,
-            &mut input,
-            &mut |_expr, input, output: &ExprOutput| {
-                if let Some(stop_after) = CFG.stop_after {
-                    if matches >= stop_after {
-                        return Ok(false);
-                    }
-                }
-                let total = input.pos();
-
-                if !output.is_empty() {
-                    if first_in_file {
-                        if CFG.pretty {
-                            writeln!(o, "{}", style(name).magenta())?;
-                        } else {
-                            writeln!(o, "{name}")?;
-                        }
-                        first_in_file = false;
-                    }
+    fn parse_not(parser: &mut Parser) -> RbrepResult<Expr> {
+        parser.trim();
+        let start = parser.pos;
+        if !parser.adv_if_trim('!') {
+            return Err(parser.error(start, "expected '!'"));
+        }
+        // the negated expression is a single atom
+        // a quantifier after it repeats the negation
+        Ok(Expr::new(ExprKind::Not {
+            expr: Box::new(Self::parse_atom(parser)?),
+        }))
+    }
 
-                    // print current buffer if match
-                    // and count is not set
-                    if !CFG.count {
-                        if CFG.pretty {
-                            write!(o, "{:08x}\t", style(total).green())?;
-                        } else {
-                            write!(o, "{:08x}\t", total)?;
-                        }
-                        for (i, b) in output.as_slice().iter().enumerate() {
-                            if CFG.space != 0 && i != 0 && i as u32 % CFG.space == 0 {
-                                write!(o, " ")?;
-                            }
-
-                            if CFG.pretty {
-                                if !b.highlight {
-                                    write!(o, "{:02x}", style(b.value))?;
-                                } else {
-                                    write!(o, "{:02x}", style(b.value).red())?;
-                                }
-                            } else {
-                                write!(o, "{:02x}", b.value)?;
-                            }
-                        }
-                        writeln!(o)?;
-                    }
-                    matches += 1;
-                }
+    fn parse(parser: &mut Parser) -> RbrepResult<Expr> {
+        let expr = Self::parse_atom(parser)?;
+        Self::parse_mul(parser, expr)
+    }
 
-                Ok(true)
-            },
-        )?;
+    // a single expression without a quantifier
+    fn parse_atom(parser: &mut Parser) -> RbrepResult<Expr> {
+        let first = parser.peek_trim();
 
-        if CFG.count {
-            writeln!(o, "{matches}")?;
+        match first {
+            '?' => Self::parse_byte_or_range(parser),
+            '(' => Self::parse_group(parser, false),
+            '{' => Self::parse_capture(parser),
+            '"' => Self::parse_string(parser),
+            '&' => Self::parse_and(parser),
+            '!' => Self::parse_not(parser),
+            'u' | 'i' | 'w' | 'W' | 'U' if Self::is_modified_string(parser) => {
+                Self::parse_modified_string(parser)
+            }
+            'u' | 'i' => Self::parse_int(parser),
+            // a $ without a name is the end of the input
+            '$' if parser.peek_at(1).is_alphanumeric() || parser.peek_at(1) == '_' => {
+                Self::parse_ref(parser)
+            }
+            '$' => {
+                parser.adv();
+                Ok(Expr::new(ExprKind::End))
+            }
+            '^' => {
+                parser.adv();
+                Ok(Expr::new(ExprKind::Start))
+            }
+            '\\' => Self::parse_backref(parser),
+            '@' => Self::parse_directive(parser),
+            _ => {
+                if first.is_ascii_hexdigit() {
+                    Self::parse_byte_or_range(parser)
+                } else if parser.is_end() {
+                    Err(parser.error(parser.pos, "unexpected end of expression"))
+                } else {
+                    Err(parser.error(parser.pos, &format!("unexpected '{first}'")))
+                }
+            }
         }
-
-        Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::*;
+    use super::Expr;
+    use crate::core::error::Error;
+    use crate::core::input::FileBufferInput;
+    use crate::core::{matcher::Matcher, options::OutputOptions, options::SearchOptions};
 
     fn validate(expected: &str, expr: &str, input: &str) {
-        let input: Vec<u8> = input.bytes().collect();
+        validate_bytes(expected, expr, input.as_bytes());
+    }
+
+    fn validate_bytes(expected: &str, expr: &str, input: &[u8]) {
         let mut output = Vec::new();
-        Expr::apply(expr, &mut input.as_slice(), &mut output, "stdin").unwrap();
+        Matcher::new(expr)
+            .unwrap()
+            .apply(
+                &mut &input[..],
+                &mut output,
+                "stdin",
+                &SearchOptions::default(),
+                &OutputOptions::default(),
+            )
+            .unwrap();
 
         let output = String::from_utf8(output).unwrap();
         assert_eq!(expected, &output);
@@ -578,6 +1344,13 @@ mod test {
         validate("stdin\n00000001\t31\n", "31", "01");
     }
 
+    #[test]
+    fn end_of_input() {
+        validate("", "30", "");
+        validate("stdin\n00000000\t3031\n", "3031", "0120");
+        validate("stdin\n00000002\t30\n", "3031*0;", "120");
+    }
+
     #[test]
     fn any() {
         validate("stdin\n00000000\t30\n00000001\t31\n", "??", "01");
@@ -646,6 +1419,596 @@ mod test {
         validate("stdin\n00000002\t3031313132\n", "3031*1+;32", "0001112");
     }
 
+    #[test]
+    fn ints() {
+        validate("stdin\n00000001\t3412\n", "u16le:0x1234", "\x00\x34\x12");
+        validate("stdin\n00000000\t1234\n", "u16be:4660", "\x12\x34");
+        validate_bytes(
+            "stdin\n00000001\tfbffffff\n",
+            "i32le:-5",
+            &[0xff, 0xfb, 0xff, 0xff, 0xff],
+        );
+        validate(
+            "stdin\n00000000\t0110\n00000002\t7f1f\n00000004\t0020\n",
+            "u16le:0x1000-0x2000",
+            "\x01\x10\x7f\x1f\x00\x20",
+        );
+        // both ends of a typed range are included
+        validate_bytes(
+            "stdin\n00000000\t00\n00000001\tff\n",
+            "u8:0x00-0xff",
+            &[0x00, 0xff],
+        );
+        validate_bytes(
+            "stdin\n00000001\t0f000000\n00000005\t10000000\n",
+            "u32le:0x10-0x0f",
+            &[0x0e, 0x0f, 0, 0, 0, 0x10, 0, 0, 0, 0x11],
+        );
+        assert_eq!(
+            (10, 13, "value does not fit into u8".into()),
+            syntax_error("u8:0x1 u8:256")
+        );
+        assert_eq!(
+            (
+                0,
+                3,
+                "expected an integer type (e.g. u8, u16le or i32be)".into()
+            ),
+            syntax_error("u16:1")
+        );
+    }
+
+    #[test]
+    fn masks() {
+        validate("stdin\n00000001\t31\n00000003\t33\n", "3?*1;", "a1b3");
+        validate("stdin\n00000000\t61\n00000001\t41\n", "?1", "aA");
+        validate("stdin\n00000000\t4142\n00000002\t5142\n", "?1?2", "ABQB");
+        validate("stdin\n00000001\t49\n00000003\t4a\n", "f8/48", "AI1J");
+        validate("stdin\n00000000\t414243\n", "(1?4?)*3;", "ABC");
+        validate("stdin\n00000001\t31\n00000002\t42\n", "0f/01-03", "01BC");
+        validate("stdin\n00000000\t30\n00000001\t41\n", "3?-5?", "0A`");
+        assert_eq!(
+            (0, 5, "value has bits outside of the mask".into()),
+            syntax_error("f0/41")
+        );
+        assert_eq!(
+            (0, 3, "a mask may not contain wildcards".into()),
+            syntax_error("f?/40")
+        );
+    }
+
+    fn syntax_error(expr: &str) -> (usize, usize, String) {
+        match Matcher::new(expr) {
+            Err(Error::BadSyntax(err)) => (err.start, err.end, err.expected),
+            _ => panic!("expected a syntax error for {expr}"),
+        }
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(
+            (7, 8, "expected ';' after multiplier".into()),
+            syntax_error("30 31*2")
+        );
+        assert_eq!(
+            (3, 6, "unterminated string".into()),
+            syntax_error("30 \"ab")
+        );
+        assert_eq!((2, 3, "unclosed group".into()), syntax_error("30(3132"));
+        assert_eq!(
+            (3, 5, "expected a hex byte (e.g. 1f or 4?)".into()),
+            syntax_error("30 3z")
+        );
+        assert_eq!((3, 4, "unexpected 'z'".into()), syntax_error("30 zz"));
+        assert_eq!((2, 3, "unexpected ')'".into()), syntax_error("30)"));
+        assert_eq!(
+            (3, 4, "expected a number after '*'".into()),
+            syntax_error("30*;")
+        );
+    }
+
+    #[test]
+    fn bounds() {
+        validate("stdin\n00000001\t3131\n00000002\t3131\n", "31{2}", "01110");
+        validate(
+            "stdin\n00000000\t3031313132\n",
+            "30 31{2,} 32",
+            "0111203132",
+        );
+        validate(
+            "stdin\n00000000\t3032\n00000002\t303132\n",
+            "30 31{,1} 32",
+            "020120112",
+        );
+        validate(
+            "stdin\n00000000\t303131\n00000004\t303131\n",
+            "30 31{1,2}",
+            "0111011",
+        );
+        validate("stdin\n00000000\t41424142\n", "(\"AB\"){2,3}", "ABABA");
+        validate("stdin\n00000001\t616263\n", "!30{3}", "0abc0");
+        validate("stdin\n00000000\t3031\n", "&(30 31){1,2}", "013");
+        assert_eq!(
+            (2, 7, "repetition minimum exceeds maximum".into()),
+            syntax_error("30{3,2}")
+        );
+        assert_eq!(
+            (2, 6, "repetition has to allow at least one match".into()),
+            syntax_error("30{,0}")
+        );
+        assert_eq!(
+            (5, 6, "expected '}' after repetition".into()),
+            syntax_error("30{3 31")
+        );
+        // repetitions are unrolled, so they can not be arbitrarily large
+        assert_eq!(
+            (2, 16, "repetition makes the pattern too large".into()),
+            syntax_error("30{0,4000000000}")
+        );
+        assert_eq!(
+            (10, 16, "repetition makes the pattern too large".into()),
+            syntax_error("(??{1000}){1000}")
+        );
+        assert_eq!(
+            (2, 14, "repetition makes the pattern too large".into()),
+            syntax_error("30*4000000000;")
+        );
+        assert_eq!(
+            (0, 21, "pattern is too large".into()),
+            syntax_error("30{500000} 31{500000}")
+        );
+    }
+
+    #[test]
+    fn backtracking() {
+        validate(
+            "stdin\n00000000\t306162454e44\n",
+            "30 ??*0+; \"END\"",
+            "0abEND",
+        );
+        validate("stdin\n00000000\t303132\n", "(30 &(3031)) 32", "012");
+        validate(
+            "stdin\n00000000\t313131\n00000001\t3131\n",
+            "31{1,3} 31",
+            "111",
+        );
+        // an empty repetition does not loop forever
+        validate(
+            "stdin\n00000000\t303031\n00000001\t3031\n00000002\t31\n",
+            "(30{0,1}){0,} 31",
+            "001",
+        );
+    }
+
+    #[test]
+    fn lazy() {
+        validate(
+            "stdin\n00000000\t3061454e4462454e44\n",
+            "30 ??{0,} \"END\"",
+            "0aENDbEND",
+        );
+        validate(
+            "stdin\n00000000\t3061454e44\n",
+            "30 ??{0,}? \"END\"",
+            "0aENDbEND",
+        );
+        validate(
+            "stdin\n00000000\t3061454e44\n",
+            "30 ??*0+?; \"END\"",
+            "0aENDbEND",
+        );
+        validate("stdin\n00000000\t3031\n", "30 31{1,3}?", "0111");
+        // a ? that starts a wildcard is not a lazy marker
+        validate("stdin\n00000000\t303031\n", "30{2}??", "001");
+        validate("stdin\n00000000\t303031\n", "30{2} ?1", "001");
+        assert_eq!(
+            (
+                5,
+                7,
+                "a ? right after a repetition makes it lazy, \
+                 put a space before a wildcard (e.g. 30{2} ?1)"
+                    .into()
+            ),
+            syntax_error("30{2}?31")
+        );
+    }
+
+    #[test]
+    fn comments() {
+        validate(
+            "stdin\n00000000\t302331\n",
+            "30 # a comment\n\t\"#\" # \"\n 31 #",
+            "0#1",
+        );
+    }
+
+    #[test]
+    fn definitions() {
+        validate(
+            "stdin\n00000001\t524946463132333457415645666d7420\n",
+            "$hdr = \"RIFF\" ??*4; \"WAVE\"\n$hdr \"fmt \"",
+            "xRIFF1234WAVEfmt ",
+        );
+        // the ; of a *n; is part of the repetition, not the end of the definition
+        assert_eq!(
+            Expr::tree_from("&(\"RIFF\" ??*4; \"WAVE\") \"fmt \"")
+                .unwrap()
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>(),
+            Expr::tree_from("$hdr = \"RIFF\" ??*4; \"WAVE\"\n$hdr \"fmt \"")
+                .unwrap()
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+        );
+        validate(
+            "stdin\n00000000\t3132333457415645\n",
+            "$len = ??*4;; $len \"WAVE\"",
+            "1234WAVE",
+        );
+        // definitions may be used before they are defined
+        validate(
+            "stdin\n00000000\t303131\n",
+            "$a $b\n$a = 30 $b # a comment\n$b = 31",
+            "0110",
+        );
+        validate(
+            "stdin\n00000000\t3030\n00000001\t3030\n",
+            "$x = 30; $x{2}",
+            "000",
+        );
+        assert_eq!(
+            (17, 19, "$a refers to itself".into()),
+            syntax_error("$a = 30 $b; $b = $a; $a")
+        );
+        assert_eq!(
+            (3, 8, "$nope is not defined".into()),
+            syntax_error("30 $nope")
+        );
+        assert_eq!(
+            (9, 11, "$a is defined twice".into()),
+            syntax_error("$a = 30; $a = 31; $a")
+        );
+        assert_eq!(
+            (0, 1, "expected an expression after '='".into()),
+            syntax_error("$a = \n 30 $a")
+        );
+        assert_eq!(
+            (
+                9,
+                23,
+                "expected an expression after the definition, \
+                 a definition ends at the end of its line or at a ';' after a '*n;'"
+                    .into()
+            ),
+            syntax_error("$a = 30; $b = 31 $a \"x\"")
+        );
+        // a $ without a name is the end of the input
+        validate("", "30 $ 31", "01");
+
+        // every definition is twice as large as the one before
+        let mut chain = "$a0 = 30\n".to_string();
+        for k in 1..32 {
+            chain += &format!("$a{k} = $a{0} $a{0}\n", k - 1);
+        }
+        chain += "$a31";
+        let start = chain.find("$a18 =").unwrap() + 7;
+        assert_eq!(
+            (start, start + 4, "$a17 makes the pattern too large".into()),
+            syntax_error(&chain)
+        );
+    }
+
+    #[test]
+    fn escapes() {
+        validate(
+            "stdin\n00000001\t485454502f312e310d0a\n",
+            "\"HTTP/1.1\\r\\n\"",
+            "xHTTP/1.1\r\n",
+        );
+        validate_bytes(
+            "stdin\n00000000\t2209005cff\n",
+            "\"\\\"\\t\\0\\\\\\xFf\"",
+            b"\"\t\0\\\xff",
+        );
+        validate("stdin\n00000000\t2341\n", "\"#\\x41\"", "#A");
+        assert_eq!(
+            (
+                2,
+                4,
+                "unknown escape '\\q', expected one of \\n \\r \\t \\0 \\\\ \\\" or \\xNN".into()
+            ),
+            syntax_error("\"a\\q\"")
+        );
+        assert_eq!(
+            (
+                1,
+                5,
+                "expected two hex digits after '\\x' (e.g. \\x1f)".into()
+            ),
+            syntax_error("\"\\x4g\"")
+        );
+        assert_eq!((0, 3, "unterminated string".into()), syntax_error("\"a\\"));
+    }
+
+    #[test]
+    fn string_modifiers() {
+        validate(
+            "stdin\n00000000\t4b65526e456c\n00000007\t6b65726e656c\n",
+            "i\"kernel\"",
+            "KeRnEl kernel kernal",
+        );
+        validate("stdin\n00000000\t412d\n", "i\"a-\"", "A-a_");
+        validate_bytes(
+            "stdin\n00000001\t53006500740075007000\n",
+            "w\"Setup\"",
+            b"xS\0e\0t\0u\0p\0",
+        );
+        validate_bytes("stdin\n00000000\t00410062\n", "W\"Ab\"", b"\0A\0b");
+        validate_bytes(
+            "stdin\n00000000\t41000000e9000000\n",
+            "u32\"Aé\"",
+            b"A\0\0\0\xe9\0\0\0",
+        );
+        validate_bytes("stdin\n00000000\t00000041\n", "U32\"A\"", b"\0\0\0A");
+        validate_bytes(
+            "stdin\n00000000\t61004200\n00000004\t41006200\n",
+            "iw\"ab\"",
+            b"a\0B\0A\0b\0a\x01b\0",
+        );
+        // escapes are code units and never change their case
+        validate_bytes("stdin\n00000000\tff00\n", "w\"\\xff\"", b"\xff\0");
+        validate("stdin\n00000000\t41\n", "i\"\\x41\"", "Aa");
+        // modifiers only apply to strings
+        validate("stdin\n00000000\t01\n", "u8:1", "\x01");
+
+        assert_eq!(
+            (
+                0,
+                2,
+                "unknown string modifier (expected i, w, W, u32 or U32)".into()
+            ),
+            syntax_error("ix\"a\"")
+        );
+        assert_eq!(
+            (0, 2, "a string can only have one encoding".into()),
+            syntax_error("wW\"a\"")
+        );
+    }
+
+    #[test]
+    fn captures() {
+        validate_bytes(
+            "stdin\n00000000\t504b03041400\tver=1400\n",
+            "\"PK\" 0304 {ver: ??*2;}",
+            b"PK\x03\x04\x14\x00",
+        );
+        validate(
+            "stdin\n00000001\t3031323334\tn=12849\ts=4\n",
+            "30 { n as u16le: 31 32 } ?? {s as str: ??}",
+            "x01234",
+        );
+        validate("stdin\n00000000\t3031\tv=49\n", "30 {v as u8: &31}", "01");
+
+        // captures of paths that did not match are dropped
+        validate("stdin\n00000000\t3032\n", "({a: 30 31} 30) 32", "02");
+        validate("stdin\n00000000\t31\tx=31\n", "({x: 30} {x: 31})", "1");
+        validate(
+            "stdin\n00000000\t3031\tx=30\ty=31\n",
+            "$h = {y: ??}\n{x: 30} $h",
+            "01",
+        );
+        validate("stdin\n00000000\t3030\n", "30{ 2}", "00");
+
+        assert_eq!(
+            (0, 12, "a u16le capture has to match exactly 2 bytes".into()),
+            syntax_error("{n as u16le: ??{1,2}}")
+        );
+        assert_eq!(
+            (8, 20, "a u16le capture has to match exactly 2 bytes".into()),
+            syntax_error("$b = ??\n{n as u16le: $b}")
+        );
+        assert_eq!(
+            (0, 1, "expected a capture name (e.g. {name: ...})".into()),
+            syntax_error("{: 30}")
+        );
+        assert_eq!(
+            (3, 4, "expected ':' after capture name".into()),
+            syntax_error("{v 30}")
+        );
+        assert_eq!(
+            (
+                6,
+                9,
+                "expected a capture format (hex, str or an integer type e.g. u16le)".into()
+            ),
+            syntax_error("{v as foo: 30}")
+        );
+        assert_eq!((0, 1, "unclosed capture".into()), syntax_error("{v: 30"));
+        assert_eq!(
+            (0, 3, "a capture has to contain an expression".into()),
+            syntax_error("{v:}")
+        );
+    }
+
+    #[test]
+    fn backrefs() {
+        validate(
+            "stdin\n00000000\t41424344787841424344\tt=41424344\n",
+            "{t: ??*4;} ??*0+?; \\t",
+            "ABCDxxABCDyy",
+        );
+        // every repetition compares against its own capture
+        validate(
+            "stdin\n00000000\t61616262\tc=62\n",
+            "&({c: ??} \\c){2}",
+            "aabbab",
+        );
+        validate("stdin\n00000001\t3031\ta=30\n", "{a: 30} !\\a", "001");
+        // a capture of a path that failed is gone
+        validate("", "(&({x: 30} 32) 30) \\x", "00");
+        // a repetition that matches nothing still ends
+        validate("", "{a: 30} (31*0;)*0+; 32 \\a", "0");
+        validate(
+            "stdin\n00000000\t303230\ta=30\n",
+            "{a: 30} (31*0;)*0+; 32 \\a",
+            "020",
+        );
+
+        assert_eq!(
+            (0, 1, "expected a capture name after '\\'".into()),
+            syntax_error("\\")
+        );
+        assert_eq!(
+            (3, 5, "there is no capture named x".into()),
+            syntax_error("30 \\x")
+        );
+        assert_eq!(
+            (7, 9, "\\c refers to a capture it is part of".into()),
+            syntax_error("{c: 30 \\c}")
+        );
+    }
+
+    #[test]
+    fn lengths() {
+        validate_bytes(
+            "stdin\n00000000\t48445203616263454e44\n",
+            "\"HDR\" @len(u8) \"END\"",
+            b"HDR\x03abcEND",
+        );
+        validate_bytes(
+            "stdin\n00000000\t530461626162\n",
+            "\"S\" @len(u8, \"ab\"{1,})",
+            b"S\x04ababS\x03abab",
+        );
+        validate_bytes(
+            "stdin\n00000000\t00017830\n",
+            "@len(u16be) 30",
+            b"\x00\x01x0",
+        );
+        // a negative length never matches
+        validate_bytes("", "@len(i8) 30", b"\xff0");
+        // a length inside of another one may not go past its end
+        validate_bytes(
+            "stdin\n00000000\t0301613030\n",
+            "@len(u8, @len(u8) 30{1,}) 30",
+            b"\x03\x01a000",
+        );
+        validate_bytes("", "@len(u8, @len(u8) ??)", b"\x02\x05abcdef");
+        // every repetition reads its own length
+        validate_bytes(
+            "stdin\n00000000\t0161026263\n",
+            "@len(u8, ??*0+;){2}",
+            b"\x01a\x02bc",
+        );
+        // a repetition that matches nothing still ends
+        validate_bytes("", "@len(u8) (31*0;)*0+; 32", b"\x00");
+        validate_bytes(
+            "stdin\n00000000\t0032\n",
+            "@len(u8) (31*0;)*0+; 32",
+            b"\x002",
+        );
+    }
+
+    #[test]
+    fn offsets() {
+        validate_bytes(
+            "stdin\n00000000\t5005\tp=5\n",
+            "\"P\" {p as u8: ??} @at(p, \"X\")",
+            b"P\x05abcX",
+        );
+        // offsets may be before the start of the match
+        validate_bytes(
+            "stdin\n00000002\t5000\tp=0\n",
+            "\"P\" {p as u8: ??} @at(p, \"X\")",
+            b"XaP\x00",
+        );
+        validate_bytes(
+            "stdin\n00000002\t50fe\tp=-2\n",
+            "\"P\" {p as i8: ??} @rel(p, \"X\")",
+            b"XaP\xfe",
+        );
+        validate_bytes(
+            "stdin\n00000002\t5003\tp=3\n",
+            "\"P\" {p as u8: ??} @rel(p, \"X\")",
+            b"aaP\x03.X",
+        );
+        validate_bytes("", "\"P\" {p as u8: ??} @at(p, \"X\")", b"aaP\x03.X");
+
+        // a streamed input can not look back past what it still buffers,
+        // the search goes on after such a candidate
+        let mut input = b"X".to_vec();
+        input.extend([0; 64]);
+        input.extend(b"P\x00P\x45X");
+        let res: Vec<_> = Matcher::new("\"P\" {p as u8: ??} @at(p, \"X\")")
+            .unwrap()
+            .find_iter_input(FileBufferInput::with_chunk_size(input.as_slice(), 4))
+            .map(|m| m.unwrap().start())
+            .collect();
+        assert_eq!(vec![67], res);
+
+        assert_eq!(
+            (0, 4, "expected @len, @at, @rel or @align".into()),
+            syntax_error("@foo(30)")
+        );
+        assert_eq!(
+            (
+                5,
+                7,
+                "expected an integer type (e.g. u8, u16le or i32be)".into()
+            ),
+            syntax_error("@len(u7)")
+        );
+        assert_eq!(
+            (8, 9, "expected an expression before ')'".into()),
+            syntax_error("@len(u8,)")
+        );
+        assert_eq!(
+            (0, 1, "unclosed directive".into()),
+            syntax_error("@len(u8, 30")
+        );
+        assert_eq!(
+            (4, 5, "there is no capture named p".into()),
+            syntax_error("@at(p, 30)")
+        );
+        assert_eq!(
+            (
+                12,
+                13,
+                "an offset has to be an integer capture (e.g. {ptr as u32le: ??*4;})".into()
+            ),
+            syntax_error("{p: ??} @at(p, 30)")
+        );
+    }
+
+    #[test]
+    fn anchors() {
+        validate("stdin\n00000000\t3031\n", "^ 30 31", "0101");
+        validate("stdin\n00000002\t3031\n", "30 31 $", "0101");
+        validate("stdin\n00000000\t30313031\n", "^ &(30 31){1,} $", "0101");
+        validate("", "^ 31", "01");
+        validate(
+            "stdin\n00000000\t30\n00000004\t30\n",
+            "@align(4) 30",
+            "000000",
+        );
+        // $name is still a reference
+        validate("stdin\n00000001\t31\n", "$a = 31\n$a $", "01");
+
+        assert_eq!(
+            (7, 8, "expected an alignment greater than 0".into()),
+            syntax_error("@align(0)")
+        );
+    }
+
+    #[test]
+    fn partial_string() {
+        // a string that only partially matches does not match at all
+        validate("stdin\n00000001\t4d5a\n", "(\"MZ\" 4d)", "xMZ");
+        validate("", "\"MZ\"", "M");
+    }
+
     #[test]
     fn many_optional() {
         validate("stdin\n00000002\t3032\n", "3031*0+;32", "0002");
@@ -1,7 +1,15 @@
-use std::io::Read;
+use std::{
+    fs::File,
+    io::{ErrorKind, Read},
+};
+
+use memmap2::Mmap;
 
 use crate::core::error::{Error, RbrepResult};
 
+// how many bytes are pulled from the reader at once
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
 pub trait MatchInput {
     // read a single byte from the input at the required offset
     fn read(&mut self, offset: usize) -> RbrepResult<u8>;
@@ -13,6 +21,22 @@ pub trait MatchInput {
         Ok(())
     }
 
+    // all bytes that are available without reading again starting at pos
+    // at least len bytes unless the input ends before that
+    fn window(&mut self, len: usize) -> RbrepResult<&[u8]>;
+
+    // like window but starting at an absolute offset of the input
+    // offsets before pos fail with Error::Unbuffered once they were dropped
+    fn window_at(&mut self, offset: usize, len: usize) -> RbrepResult<&[u8]>;
+
+    // read a single byte at an absolute offset of the input
+    fn read_at(&mut self, offset: usize) -> RbrepResult<u8> {
+        self.window_at(offset, 1)?
+            .first()
+            .copied()
+            .ok_or(Error::EndOfFile)
+    }
+
     // current read cursor position
     // should be the start offset
     // from which a match begins
@@ -23,78 +47,262 @@ pub trait MatchInput {
     fn eof(&self) -> bool;
 }
 
-pub struct FileBufferInput<'a> {
+impl<T: MatchInput + ?Sized> MatchInput for &mut T {
+    fn read(&mut self, offset: usize) -> RbrepResult<u8> {
+        (**self).read(offset)
+    }
+
+    fn advance(&mut self, by: usize) -> RbrepResult<()> {
+        (**self).advance(by)
+    }
+
+    fn window(&mut self, len: usize) -> RbrepResult<&[u8]> {
+        (**self).window(len)
+    }
+
+    fn window_at(&mut self, offset: usize, len: usize) -> RbrepResult<&[u8]> {
+        (**self).window_at(offset, len)
+    }
+
+    fn pos(&self) -> usize {
+        (**self).pos()
+    }
+
+    fn eof(&self) -> bool {
+        (**self).eof()
+    }
+}
+
+// Streaming input that reads large chunks from the reader.
+// Only the window starting at pos is kept around, bytes before pos
+// are dropped once at least a full chunk has been consumed.
+pub struct FileBufferInput<R: Read> {
     buffer: Vec<u8>,
-    read: &'a mut dyn Read,
+    // index of pos inside of buffer
+    start: usize,
+    read: R,
+    chunk_size: usize,
     eof: bool,
     pos: usize,
 }
 
-impl<'a> FileBufferInput<'a> {
-    pub fn new(read: &'a mut dyn Read) -> Self {
+impl<R: Read> FileBufferInput<R> {
+    pub fn new(read: R) -> Self {
+        Self::with_chunk_size(read, DEFAULT_CHUNK_SIZE)
+    }
+
+    pub fn with_chunk_size(read: R, chunk_size: usize) -> Self {
         Self {
             buffer: vec![],
+            start: 0,
             read,
+            chunk_size: chunk_size.max(1),
             eof: false,
             pos: 0,
         }
     }
 
-    fn remove(&mut self, offset: usize) {
-        if self.buffer.len() > offset {
-            self.buffer.remove(offset);
+    // amount of bytes that are buffered starting at pos
+    fn available(&self) -> usize {
+        self.buffer.len() - self.start
+    }
+
+    // drop everything before pos
+    // this only happens once a full chunk is wasted
+    // to avoid moving the buffer around on every advance
+    fn compact(&mut self) {
+        if self.start >= self.chunk_size {
+            self.buffer.drain(..self.start);
+            self.start = 0;
         }
     }
 
-    fn read_next(&mut self) -> RbrepResult<usize> {
-        let mut next = [0; 1];
-        // read a new byte
-        let res = self.read.read_exact(&mut next);
-        // same here, if it is eof
-        // we have simply reached the onf of the file!
-        match res {
-            Err(ref e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
-                self.eof = true;
-                Ok(0)
-            }
-            Err(_) => Err(Error::Io),
-            _ => {
-                self.buffer.push(next[0]);
-                Ok(1)
+    // make sure at least n bytes starting at pos are buffered
+    // unless the reader is out of data
+    fn fill(&mut self, n: usize) -> RbrepResult<()> {
+        while self.available() < n && !self.eof {
+            self.compact();
+
+            let len = self.buffer.len();
+            self.buffer.resize(len + self.chunk_size, 0);
+            let res = self.read.read(&mut self.buffer[len..]);
+            match res {
+                Ok(0) => {
+                    self.buffer.truncate(len);
+                    self.eof = true;
+                }
+                Ok(read) => self.buffer.truncate(len + read),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => self.buffer.truncate(len),
+                Err(e) => {
+                    self.buffer.truncate(len);
+                    return Err(Error::Io(e));
+                }
             }
         }
+        Ok(())
     }
 }
 
-impl<'a> MatchInput for FileBufferInput<'a> {
+impl<R: Read> MatchInput for FileBufferInput<R> {
     fn read(&mut self, offset: usize) -> RbrepResult<u8> {
-        let mut next = [0; 1];
-        if let Some(val) = self.buffer.get(offset) {
-            Ok(*val)
-        } else if self.read.read_exact(&mut next).is_err() {
-            // FIXME this may not be correct
-            self.eof = true;
-            Err(Error::EndOfFile)
-        } else {
-            self.buffer.push(next[0]);
-            Ok(next[0])
-        }
+        self.fill(offset + 1)?;
+        self.buffer
+            .get(self.start + offset)
+            .copied()
+            .ok_or(Error::EndOfFile)
     }
 
     fn advance(&mut self, by: usize) -> RbrepResult<()> {
+        // read one byte past the new position
+        // so that eof is known right away
+        self.fill(by + 1)?;
         self.pos += by;
-        for _ in 0..by {
-            self.remove(0);
-            self.read_next()?;
-        }
+        self.start = (self.start + by).min(self.buffer.len());
+        Ok(())
+    }
+
+    fn window(&mut self, len: usize) -> RbrepResult<&[u8]> {
+        self.fill(len.max(self.chunk_size))?;
+        Ok(&self.buffer[self.start..])
+    }
+
+    // bytes before pos are kept until the buffer is compacted
+    fn window_at(&mut self, offset: usize, len: usize) -> RbrepResult<&[u8]> {
+        self.fill((offset + len).saturating_sub(self.pos))?;
+        let index = (self.start + offset)
+            .checked_sub(self.pos)
+            .ok_or(Error::Unbuffered(offset))?;
+        Ok(&self.buffer[index.min(self.buffer.len())..])
+    }
+
+    fn pos(&self) -> usize {
+        self.pos
+    }
+
+    fn eof(&self) -> bool {
+        self.eof && self.available() == 0
+    }
+}
+
+// Input backed by a memory map of a regular file.
+// The whole file is addressable without copying anything.
+pub struct MmapInput {
+    map: Mmap,
+    pos: usize,
+}
+
+impl MmapInput {
+    pub fn new(file: &File) -> RbrepResult<Self> {
+        // SAFETY: the map is only ever read from. Like every other
+        // mmap based grep we accept that truncating the file while it is
+        // being searched may bring the process down.
+        let map = unsafe { Mmap::map(file) }?;
+        Ok(Self { map, pos: 0 })
+    }
+}
+
+impl MatchInput for MmapInput {
+    fn read(&mut self, offset: usize) -> RbrepResult<u8> {
+        self.map
+            .get(self.pos + offset)
+            .copied()
+            .ok_or(Error::EndOfFile)
+    }
+
+    fn advance(&mut self, by: usize) -> RbrepResult<()> {
+        self.pos = (self.pos + by).min(self.map.len());
         Ok(())
     }
 
+    fn window(&mut self, _len: usize) -> RbrepResult<&[u8]> {
+        Ok(&self.map[self.pos..])
+    }
+
+    // the whole file stays mapped, even the bytes before pos
+    fn window_at(&mut self, offset: usize, _len: usize) -> RbrepResult<&[u8]> {
+        Ok(self.map.get(offset..).unwrap_or_default())
+    }
+
     fn pos(&self) -> usize {
         self.pos
     }
 
     fn eof(&self) -> bool {
-        self.eof
+        self.pos >= self.map.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn read_across_chunks() {
+        let data: Vec<u8> = (0..=255).collect();
+        let mut input = FileBufferInput::with_chunk_size(data.as_slice(), 7);
+
+        for i in 0..256 {
+            assert_eq!(i, input.pos());
+            assert_eq!(i as u8, input.read(0).unwrap());
+            if i <= 250 {
+                assert_eq!(i as u8 + 5, input.read(5).unwrap());
+            } else {
+                assert!(matches!(input.read(5), Err(Error::EndOfFile)));
+            }
+            assert!(!input.eof());
+            input.advance(1).unwrap();
+        }
+        assert!(input.eof());
+    }
+
+    #[test]
+    fn buffer_stays_small() {
+        let data = vec![0u8; 1024 * 1024];
+        let mut input = FileBufferInput::with_chunk_size(data.as_slice(), 16);
+
+        while !input.eof() {
+            input.read(3).ok();
+            input.advance(1).unwrap();
+            assert!(input.buffer.len() <= 64);
+        }
+        assert_eq!(data.len(), input.pos());
+    }
+
+    #[test]
+    fn window() {
+        let data: Vec<u8> = (0..=255).collect();
+        let mut input = FileBufferInput::with_chunk_size(data.as_slice(), 16);
+        assert_eq!(&data[..16], input.window(4).unwrap());
+        input.advance(10).unwrap();
+        assert_eq!(&data[10..50], &input.window(40).unwrap()[..40]);
+        input.advance(240).unwrap();
+        assert_eq!(&data[250..], input.window(40).unwrap());
+    }
+
+    #[test]
+    fn empty() {
+        let data: Vec<u8> = vec![];
+        let mut input = FileBufferInput::new(data.as_slice());
+        assert!(matches!(input.read(0), Err(Error::EndOfFile)));
+        assert!(input.eof());
+    }
+
+    #[test]
+    fn mmap() {
+        let path = std::env::temp_dir().join(format!("rbrep-mmap-{}", std::process::id()));
+        File::create(&path).unwrap().write_all(b"0123").unwrap();
+
+        let mut input = MmapInput::new(&File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(b'0', input.read(0).unwrap());
+        assert_eq!(b'3', input.read(3).unwrap());
+        assert!(matches!(input.read(4), Err(Error::EndOfFile)));
+        input.advance(2).unwrap();
+        assert_eq!(2, input.pos());
+        assert_eq!(b'3', input.read(1).unwrap());
+        input.advance(2).unwrap();
+        assert!(input.eof());
     }
 }
@@ -0,0 +1,261 @@
+use std::fmt::Display;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+// integer type of a typed literal e.g. u32le
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IntType {
+    // size in bytes
+    pub size: usize,
+    pub signed: bool,
+    pub endian: Endian,
+}
+
+impl IntType {
+    // parse a type name such as u8, i16le or u32be
+    // 8 bit types do not need an endianness
+    pub fn from_name(name: &str) -> Option<Self> {
+        let signed = match name.chars().next()? {
+            'u' => false,
+            'i' => true,
+            _ => return None,
+        };
+
+        let name = &name[1..];
+        let (bits, endian) = if let Some(bits) = name.strip_suffix("le") {
+            (bits, Some(Endian::Little))
+        } else if let Some(bits) = name.strip_suffix("be") {
+            (bits, Some(Endian::Big))
+        } else {
+            (name, None)
+        };
+
+        let size = match bits {
+            "8" => 1,
+            "16" => 2,
+            "32" => 4,
+            "64" => 8,
+            _ => return None,
+        };
+
+        let endian = match endian {
+            Some(endian) => endian,
+            None if size == 1 => Endian::Little,
+            None => return None,
+        };
+
+        Some(Self {
+            size,
+            signed,
+            endian,
+        })
+    }
+
+    pub fn min(&self) -> i128 {
+        if self.signed {
+            -(1 << (self.size * 8 - 1))
+        } else {
+            0
+        }
+    }
+
+    pub fn max(&self) -> i128 {
+        if self.signed {
+            (1 << (self.size * 8 - 1)) - 1
+        } else {
+            (1 << (self.size * 8)) - 1
+        }
+    }
+
+    pub fn contains(&self, value: i128) -> bool {
+        (self.min()..=self.max()).contains(&value)
+    }
+
+    // the bytes of a value, the value has to fit into the type
+    pub fn encode(&self, value: i128) -> Vec<u8> {
+        let mut bytes = value.to_le_bytes()[..self.size].to_vec();
+        if self.endian == Endian::Big {
+            bytes.reverse();
+        }
+        bytes
+    }
+
+    // The bytes of every value in from..=to as sequences of byte ranges
+    // in the order the bytes are stored.
+    // A value is in the range if its bytes are in every range of one of the sequences.
+    pub fn byte_ranges(&self, from: i128, to: i128) -> Vec<Vec<(u8, u8)>> {
+        // a negative value is stored as 2^bits + value,
+        // so both halves of a signed range are contiguous on their own
+        let bits = self.size * 8;
+        let mut ranges = vec![];
+        if from < 0 {
+            ranges.extend(split_range(
+                (from + (1 << bits)) as u128,
+                (to.min(-1) + (1 << bits)) as u128,
+                self.size,
+            ));
+        }
+        if to >= 0 {
+            ranges.extend(split_range(from.max(0) as u128, to as u128, self.size));
+        }
+
+        if self.endian == Endian::Little {
+            ranges.iter_mut().for_each(|r| r.reverse());
+        }
+        ranges
+    }
+
+    // turn size bytes back into a value
+    pub fn decode(&self, bytes: &[u8]) -> i128 {
+        let mut value: u64 = 0;
+        for i in 0..self.size {
+            let b = match self.endian {
+                Endian::Little => bytes[self.size - 1 - i],
+                Endian::Big => bytes[i],
+            };
+            value = (value << 8) | b as u64;
+        }
+
+        if self.signed {
+            // sign extend
+            let shift = 64 - self.size * 8;
+            ((value << shift) as i64 >> shift) as i128
+        } else {
+            value as i128
+        }
+    }
+}
+
+// split lo..=hi of n byte big endian values into sequences of byte ranges
+fn split_range(lo: u128, hi: u128, n: usize) -> Vec<Vec<(u8, u8)>> {
+    if n == 1 {
+        return vec![vec![(lo as u8, hi as u8)]];
+    }
+
+    let shift = 8 * (n - 1);
+    let mask = (1 << shift) - 1;
+    let (lo_head, lo_tail) = (lo >> shift, lo & mask);
+    let (hi_head, hi_tail) = (hi >> shift, hi & mask);
+    let prefixed = |head: u128, lo, hi| {
+        split_range(lo, hi, n - 1).into_iter().map(move |tail| {
+            let mut seq = vec![(head as u8, head as u8)];
+            seq.extend(tail);
+            seq
+        })
+    };
+
+    if lo_head == hi_head {
+        return prefixed(lo_head, lo_tail, hi_tail).collect();
+    }
+
+    // the first and last head may only allow part of the tails,
+    // every head in between allows all of them
+    let mut out = vec![];
+    let (mut first, mut last) = (lo_head, hi_head);
+    if lo_tail != 0 {
+        out.extend(prefixed(lo_head, lo_tail, mask));
+        first += 1;
+    }
+    if hi_tail != mask {
+        last -= 1;
+    }
+    if first <= last {
+        let mut seq = vec![(first as u8, last as u8)];
+        seq.resize(n, (0, 0xff));
+        out.push(seq);
+    }
+    if hi_tail != mask {
+        out.extend(prefixed(hi_head, 0, hi_tail));
+    }
+    out
+}
+
+impl Display for IntType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.signed { 'i' } else { 'u' };
+        let endian = match (self.size, self.endian) {
+            (1, _) => "",
+            (_, Endian::Little) => "le",
+            (_, Endian::Big) => "be",
+        };
+        write!(f, "{sign}{}{endian}", self.size * 8)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn names() {
+        let ty = IntType::from_name("u32le").unwrap();
+        assert_eq!((4, false, Endian::Little), (ty.size, ty.signed, ty.endian));
+        assert_eq!("u32le", ty.to_string());
+        assert_eq!("i8", IntType::from_name("i8").unwrap().to_string());
+        assert_eq!("i64be", IntType::from_name("i64be").unwrap().to_string());
+        assert!(IntType::from_name("u16").is_none());
+        assert!(IntType::from_name("u24le").is_none());
+        assert!(IntType::from_name("f32le").is_none());
+    }
+
+    #[test]
+    fn encode_decode() {
+        let ty = IntType::from_name("u16le").unwrap();
+        assert_eq!(vec![0x34, 0x12], ty.encode(0x1234));
+        assert_eq!(0x1234, ty.decode(&[0x34, 0x12]));
+
+        let ty = IntType::from_name("i32be").unwrap();
+        assert_eq!(vec![0xff, 0xff, 0xff, 0xfb], ty.encode(-5));
+        assert_eq!(-5, ty.decode(&[0xff, 0xff, 0xff, 0xfb]));
+        assert!(!ty.contains(0x80000000));
+
+        let ty = IntType::from_name("i64le").unwrap();
+        assert_eq!(-2, ty.decode(&ty.encode(-2)));
+        let ty = IntType::from_name("u64le").unwrap();
+        assert_eq!(u64::MAX as i128, ty.decode(&ty.encode(u64::MAX as i128)));
+    }
+
+    #[test]
+    fn byte_ranges() {
+        let ty = IntType::from_name("u16be").unwrap();
+        assert_eq!(
+            vec![
+                vec![(0x12, 0x12), (0xf0, 0xff)],
+                vec![(0x13, 0x13), (0x00, 0x01)]
+            ],
+            ty.byte_ranges(0x12f0, 0x1301)
+        );
+
+        // every value of the type is in exactly one sequence
+        let matches = |ranges: &[Vec<(u8, u8)>], bytes: &[u8]| {
+            ranges
+                .iter()
+                .filter(|seq| {
+                    seq.iter()
+                        .zip(bytes)
+                        .all(|((lo, hi), b)| (lo..=hi).contains(&b))
+                })
+                .count()
+        };
+        for (name, from, to) in [
+            ("u16le", 0x00ff, 0x2301),
+            ("i16be", -300, 2),
+            ("i16le", -2, -1),
+        ] {
+            let ty = IntType::from_name(name).unwrap();
+            let ranges = ty.byte_ranges(from, to);
+            for value in ty.min()..=ty.max() {
+                let expected = (from..=to).contains(&value) as usize;
+                assert_eq!(
+                    expected,
+                    matches(&ranges, &ty.encode(value)),
+                    "{name} {value}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,407 @@
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+};
+
+use crate::core::{
+    dfa::{Dfa, DfaCache, EarliestEnd},
+    error::{Error, RbrepResult},
+    expr::{Expr, ExprBranch, ExprKind},
+    input::{FileBufferInput, MatchInput},
+    options::{OutputOptions, SearchOptions},
+    output::{ExprOutput, Match, MatchOutput},
+    prefilter::Prefilter,
+    program::Program,
+    search::{Search, Searcher},
+};
+
+// A matcher is an expression that was parsed and validated once.
+// It can then be applied to any number of inputs.
+#[derive(Clone)]
+pub struct Matcher {
+    tree: ExprBranch,
+    program: Program,
+    // None if the program can not be run as a dfa
+    dfa: Option<Dfa>,
+    // None if the expression does not contain a literal
+    prefilter: Option<Prefilter>,
+    min_len: usize,
+    max_len: Option<usize>,
+    // only matches at the start of the input
+    anchored: bool,
+}
+
+impl Matcher {
+    pub fn new(src: &str) -> RbrepResult<Self> {
+        Self::from_tree(Expr::tree_from(src)?)
+    }
+
+    pub fn from_tree(tree: ExprBranch) -> RbrepResult<Self> {
+        if tree.is_empty() {
+            return Err(Error::EmptyExpr);
+        }
+
+        let min_len = Expr::min_len_all(&tree);
+        let max_len = Expr::max_len_all(&tree);
+        let program = Program::compile(&tree);
+        let anchored = tree
+            .first()
+            .is_some_and(|e| matches!(e.kind(), ExprKind::Start) && e.min() > 0);
+        Ok(Self {
+            anchored,
+            dfa: Dfa::new(&program),
+            prefilter: Prefilter::new(&tree),
+            program,
+            tree,
+            min_len,
+            max_len,
+        })
+    }
+
+    pub fn tree(&self) -> &ExprBranch {
+        &self.tree
+    }
+
+    // the least amount of bytes a match can consist of
+    pub fn min_len(&self) -> usize {
+        self.min_len
+    }
+
+    // the most bytes a match can consist of
+    // None if the expression contains a repetition without upper bound
+    pub fn max_len(&self) -> Option<usize> {
+        self.max_len
+    }
+
+    pub fn start_match<IF, OF>(&self, reader: &mut IF) -> RbrepResult<OF>
+    where
+        IF: MatchInput,
+        OF: MatchOutput,
+    {
+        let mut res = OF::default();
+        if !self.program.exec(reader, &mut res, false)? {
+            return Ok(OF::default());
+        }
+        Ok(res)
+    }
+
+    // the match that starts at the current position of the input if there is one
+    // the dfa decides if there is a match, the vm only runs to
+    // find out which bytes are part of it
+    pub fn match_here<IF: MatchInput>(
+        &self,
+        reader: &mut IF,
+        cache: Option<&mut DfaCache>,
+    ) -> RbrepResult<Option<Match>> {
+        if let Some(cache) = cache {
+            if !cache.is_match(reader)? {
+                return Ok(None);
+            }
+        }
+
+        let res: ExprOutput = self.start_match(reader)?;
+        if res.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Match::new(
+            reader.pos(),
+            res.as_slice(),
+            res.captures(),
+        )))
+    }
+
+    // true if the expression starts with ^
+    pub fn anchored(&self) -> bool {
+        self.anchored
+    }
+
+    pub fn dfa(&self) -> Option<&Dfa> {
+        self.dfa.as_ref()
+    }
+
+    pub fn prefilter(&self) -> Option<&Prefilter> {
+        self.prefilter.as_ref()
+    }
+
+    // iterate over all matches in a reader
+    pub fn find_iter<R: Read>(&self, reader: R) -> Matches<'_, FileBufferInput<R>> {
+        self.find_iter_input(FileBufferInput::new(reader))
+    }
+
+    pub fn find_iter_input<IF: MatchInput>(&self, input: IF) -> Matches<'_, IF> {
+        Search::new(
+            MatcherSearcher {
+                matcher: self,
+                cache: self.dfa.as_ref().map(DfaCache::new),
+                next_end: 0,
+            },
+            input,
+        )
+    }
+
+    // advance to the next offset at which a match may start
+    // returns false if there is none
+    fn skip<IF: MatchInput>(&self, reader: &mut IF) -> RbrepResult<bool> {
+        match &self.prefilter {
+            Some(prefilter) => prefilter.skip(reader),
+            None => Ok(true),
+        }
+    }
+
+    // search a reader by streaming it in chunks
+    pub fn apply(
+        &self,
+        i: &mut dyn Read,
+        o: &mut dyn Write,
+        name: &str,
+        search: &SearchOptions,
+        output: &OutputOptions,
+    ) -> anyhow::Result<usize> {
+        self.apply_input(&mut FileBufferInput::new(i), o, name, search, output)
+    }
+
+    pub fn apply_input<IF>(
+        &self,
+        input: &mut IF,
+        o: &mut dyn Write,
+        name: &str,
+        search: &SearchOptions,
+        output: &OutputOptions,
+    ) -> anyhow::Result<usize>
+    where
+        IF: MatchInput,
+    {
+        self.find_iter_input(input)
+            .write_matches(o, name, search, output, |o, m| {
+                output.write_match(o, name, &m)
+            })
+    }
+}
+
+// Iterator over every match of a matcher in an input.
+pub type Matches<'m, IF> = Search<IF, MatcherSearcher<'m>>;
+
+// Finds the matches of a single matcher.
+pub struct MatcherSearcher<'m> {
+    matcher: &'m Matcher,
+    cache: Option<DfaCache<'m>>,
+    // no match that starts before this offset ends before it
+    next_end: usize,
+}
+
+impl Searcher for MatcherSearcher<'_> {
+    type Item = Match;
+
+    fn next_start<IF: MatchInput>(&mut self, input: &mut IF) -> RbrepResult<Option<usize>> {
+        while !input.eof() {
+            if !self.matcher.skip(input)? {
+                return Ok(None);
+            }
+            let start = input.pos();
+
+            // nothing after the start of the input can match
+            if self.matcher.anchored && start != 0 {
+                return Ok(None);
+            }
+
+            // the vm takes over once the dfa gave up
+            if self.cache.as_ref().is_some_and(DfaCache::is_full) {
+                self.cache = None;
+            }
+            if let Some(cache) = &mut self.cache {
+                if start >= self.next_end {
+                    match cache.earliest_end(input)? {
+                        EarliestEnd::At { dead, end } => {
+                            self.next_end = start + end;
+                            // only the starts after the last dead one can end there
+                            if dead > 0 {
+                                input.advance(dead)?;
+                                continue;
+                            }
+                        }
+                        // nothing left to find
+                        EarliestEnd::Never => return Ok(None),
+                        // the search goes on at the next offset
+                        EarliestEnd::Pending(skip) if skip > 0 => {
+                            input.advance(skip)?;
+                            continue;
+                        }
+                        EarliestEnd::Pending(_) => {}
+                    }
+                }
+            }
+            return Ok(Some(start));
+        }
+        Ok(None)
+    }
+
+    fn matches_here<IF: MatchInput>(
+        &mut self,
+        input: &mut IF,
+        found: &mut VecDeque<Match>,
+    ) -> RbrepResult<()> {
+        found.extend(self.matcher.match_here(input, self.cache.as_mut())?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lengths() {
+        let matcher = Matcher::new("30\"Hi\"(31&(3233))").unwrap();
+        assert_eq!(4, matcher.min_len());
+        assert_eq!(Some(5), matcher.max_len());
+
+        let matcher = Matcher::new("30*0;31*2+;").unwrap();
+        assert_eq!(2, matcher.min_len());
+        assert_eq!(None, matcher.max_len());
+
+        let matcher = Matcher::new("(30 \"Hi\"){2,4} 31{,3}").unwrap();
+        assert_eq!(2, matcher.min_len());
+        assert_eq!(Some(11), matcher.max_len());
+    }
+
+    #[test]
+    fn find_iter() {
+        let matcher = Matcher::new("30??*0;").unwrap();
+        let found: Vec<Match> = matcher
+            .find_iter("a0b0".as_bytes())
+            .collect::<RbrepResult<_>>()
+            .unwrap();
+
+        assert_eq!(2, found.len());
+        assert_eq!((1, 2), (found[0].start(), found[0].len()));
+        assert_eq!(b"0b", found[0].bytes());
+        assert_eq!(&[true, false], found[0].highlight());
+        assert_eq!((3, 1), (found[1].start(), found[1].len()));
+
+        let starts: Vec<usize> = matcher
+            .find_iter("000000".as_bytes())
+            .filter_map(|m| m.ok())
+            .filter(|m| m.len() == 2)
+            .map(|m| m.start())
+            .take(3)
+            .collect();
+        assert_eq!(vec![0, 1, 2], starts);
+    }
+
+    #[test]
+    fn empty() {
+        assert!(matches!(Matcher::new(""), Err(Error::EmptyExpr)));
+        assert!(matches!(Matcher::new("  "), Err(Error::EmptyExpr)));
+    }
+
+    #[test]
+    fn options() {
+        let matcher = Matcher::new("&30").unwrap();
+        let run = |search: SearchOptions, output: OutputOptions| {
+            let mut o = vec![];
+            matcher
+                .apply(&mut "0123".as_bytes(), &mut o, "stdin", &search, &output)
+                .unwrap();
+            String::from_utf8(o).unwrap()
+        };
+
+        assert_eq!(
+            "stdin\n00000000\t30\n00000001\t31\n",
+            run(
+                SearchOptions {
+                    stop_after: Some(2),
+                    ..Default::default()
+                },
+                OutputOptions::default()
+            )
+        );
+        assert_eq!(
+            "stdin\n00000000\t30\n00000002\t32\n",
+            run(
+                SearchOptions {
+                    align: Some(2),
+                    ..Default::default()
+                },
+                OutputOptions::default()
+            )
+        );
+        assert_eq!(
+            "stdin\n4\n",
+            run(
+                SearchOptions::default(),
+                OutputOptions {
+                    count: true,
+                    ..Default::default()
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn captures() {
+        let matcher = Matcher::new("30 {n as u8: ??} {s as str: ??*2;}").unwrap();
+        let m = matcher
+            .find_iter("x0\x05\"\n".as_bytes())
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(2, m.captures().len());
+        let n = m.capture("n").unwrap();
+        assert_eq!((1, 2), (n.start(), n.end()));
+        assert_eq!(&[5], m.capture_bytes(n));
+        assert_eq!("\\\"\\n", m.capture("s").unwrap().format().format(b"\"\n"));
+
+        let mut o = vec![];
+        matcher
+            .apply(
+                &mut "x0\x05\"\n".as_bytes(),
+                &mut o,
+                "a\"b",
+                &SearchOptions::default(),
+                &OutputOptions {
+                    json: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            concat!(
+                r#"{"file":"a\"b","offset":1,"bytes":"3005220a","captures":["#,
+                r#"{"name":"n","offset":2,"len":1,"value":5},"#,
+                r#"{"name":"s","offset":3,"len":2,"value":"\"\n"}]}"#,
+                "\n"
+            ),
+            String::from_utf8(o).unwrap()
+        );
+    }
+
+    #[test]
+    fn shared() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Matcher>();
+
+        let matcher = std::sync::Arc::new(Matcher::new("31").unwrap());
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let matcher = matcher.clone();
+                std::thread::spawn(move || {
+                    let mut output = vec![];
+                    matcher
+                        .apply(
+                            &mut "0123".as_bytes(),
+                            &mut output,
+                            "stdin",
+                            &SearchOptions::default(),
+                            &OutputOptions::default(),
+                        )
+                        .unwrap();
+                    String::from_utf8(output).unwrap()
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!("stdin\n00000001\t31\n", handle.join().unwrap());
+        }
+    }
+}
@@ -1,6 +1,16 @@
 pub mod config;
+pub mod dfa;
 pub mod error;
+pub mod exec;
 pub mod expr;
 pub mod input;
+pub mod int;
+pub mod matcher;
+pub mod options;
 pub mod output;
 pub mod parser;
+pub mod prefilter;
+pub mod program;
+pub mod search;
+pub mod set;
+pub mod walk;
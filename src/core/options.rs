@@ -0,0 +1,47 @@
+// options that change which matches are reported
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    // stop searching a file after n matches
+    pub stop_after: Option<usize>,
+
+    // only report matches that start at a multiple of n
+    pub align: Option<usize>,
+}
+
+// options that change how matches are written
+#[derive(Debug, Clone, Default)]
+pub struct OutputOptions {
+    // colored output
+    pub pretty: bool,
+
+    // only print the amount of matches per file
+    pub count: bool,
+
+    // insert a space every n bytes, 0 disables spacing
+    pub space: u32,
+
+    // do not write anything at all
+    pub quiet: bool,
+
+    // write every match as a line of json
+    pub json: bool,
+}
+
+// options that change which files are searched
+#[derive(Debug, Clone, Default)]
+pub struct WalkOptions {
+    // descend into directories
+    pub recursive: bool,
+
+    // only search files matching any of these globs
+    pub include: Vec<String>,
+
+    // skip files and directories matching any of these globs
+    pub exclude: Vec<String>,
+
+    // follow symbolic links while walking directories
+    pub follow: bool,
+
+    // do not descend more than n directories
+    pub max_depth: Option<usize>,
+}
@@ -1,13 +1,28 @@
+use std::io::Write;
+
+use console::style;
+
+use crate::core::{int::IntType, options::OutputOptions};
+
 pub trait MatchOutput: Clone + Default {
     // add new byte to output
     fn push(&mut self, out: ExprOutData);
 
+    // drop everything after len
+    // used to undo a partial match
+    fn truncate(&mut self, len: usize);
+
     // how many bytes were already written
     fn len(&self) -> usize;
 
     fn is_empty(&self) -> bool;
 
     fn as_slice(&self) -> &[ExprOutData];
+
+    // add a capture of a finished match
+    fn capture(&mut self, capture: Capture);
+
+    fn captures(&self) -> &[Capture];
 }
 
 #[derive(Clone, Default, Debug)]
@@ -22,9 +37,139 @@ impl ExprOutData {
     }
 }
 
+// how the bytes of a capture are reported
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CaptureFormat {
+    #[default]
+    Hex,
+    // text, bytes that are not printable are escaped
+    Str,
+    // an integer, the capture is exactly as large as the type
+    Int(IntType),
+}
+
+impl CaptureFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "hex" => Some(Self::Hex),
+            "str" => Some(Self::Str),
+            _ => IntType::from_name(name).map(Self::Int),
+        }
+    }
+
+    pub fn format(&self, bytes: &[u8]) -> String {
+        match self {
+            Self::Hex => bytes.iter().map(|b| format!("{b:02x}")).collect(),
+            Self::Str => bytes.escape_ascii().to_string(),
+            Self::Int(ty) => ty.decode(bytes).to_string(),
+        }
+    }
+}
+
+// A named part of a match
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Capture {
+    name: String,
+    format: CaptureFormat,
+    // offsets relative to the start of the match
+    start: usize,
+    end: usize,
+}
+
+impl Capture {
+    pub fn new(name: &str, format: CaptureFormat, start: usize, end: usize) -> Self {
+        Self {
+            name: name.into(),
+            format,
+            start,
+            end,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn format(&self) -> CaptureFormat {
+        self.format
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+}
+
+// A single match found in an input
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Match {
+    start: usize,
+    bytes: Vec<u8>,
+    highlight: Vec<bool>,
+    captures: Vec<Capture>,
+}
+
+impl Match {
+    pub fn new(start: usize, data: &[ExprOutData], captures: &[Capture]) -> Self {
+        Self {
+            start,
+            bytes: data.iter().map(|d| d.value).collect(),
+            highlight: data.iter().map(|d| d.highlight).collect(),
+            captures: captures.to_vec(),
+        }
+    }
+
+    // offset of the first matched byte in the input
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    // offset one past the last matched byte
+    pub fn end(&self) -> usize {
+        self.start + self.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    // true for every byte that was matched by a value
+    // and false for bytes that were matched by a wildcard
+    pub fn highlight(&self) -> &[bool] {
+        &self.highlight
+    }
+
+    // every capture that took part in the match in the order they start
+    pub fn captures(&self) -> &[Capture] {
+        &self.captures
+    }
+
+    // the bytes of a capture of this match
+    pub fn capture_bytes(&self, capture: &Capture) -> &[u8] {
+        &self.bytes[capture.start..capture.end]
+    }
+
+    // the first capture with this name
+    pub fn capture(&self, name: &str) -> Option<&Capture> {
+        self.captures.iter().find(|c| c.name == name)
+    }
+}
+
 #[derive(Default, Clone)]
 pub struct ExprOutput {
     data: Vec<ExprOutData>,
+    captures: Vec<Capture>,
 }
 
 impl MatchOutput for ExprOutput {
@@ -32,6 +177,10 @@ impl MatchOutput for ExprOutput {
         self.data.push(out);
     }
 
+    fn truncate(&mut self, len: usize) {
+        self.data.truncate(len);
+    }
+
     fn len(&self) -> usize {
         self.data.len()
     }
@@ -43,4 +192,158 @@ impl MatchOutput for ExprOutput {
     fn is_empty(&self) -> bool {
         self.data.is_empty()
     }
+
+    fn capture(&mut self, capture: Capture) {
+        self.captures.push(capture);
+    }
+
+    fn captures(&self) -> &[Capture] {
+        &self.captures
+    }
+}
+
+impl OutputOptions {
+    // the header in front of the matches of an input
+    pub fn write_name(&self, o: &mut dyn Write, name: &str) -> std::io::Result<()> {
+        if self.json {
+            // every json line names its input
+            Ok(())
+        } else if self.pretty {
+            writeln!(o, "{}", style(name).magenta())
+        } else {
+            writeln!(o, "{name}")
+        }
+    }
+
+    pub fn write_count(&self, o: &mut dyn Write, name: &str, count: usize) -> std::io::Result<()> {
+        if self.json {
+            writeln!(o, "{{\"file\":{},\"count\":{count}}}", json_string(name))
+        } else {
+            writeln!(o, "{count}")
+        }
+    }
+
+    pub fn write_match(&self, o: &mut dyn Write, name: &str, m: &Match) -> std::io::Result<()> {
+        if self.json {
+            return self.write_json(o, name, None, m);
+        }
+        self.write_offset(o, m)?;
+        self.write_bytes(o, m)
+    }
+
+    // a match tagged with the name of the pattern that found it
+    pub fn write_pattern_match(
+        &self,
+        o: &mut dyn Write,
+        name: &str,
+        pattern: &str,
+        m: &Match,
+    ) -> std::io::Result<()> {
+        if self.json {
+            return self.write_json(o, name, Some(pattern), m);
+        }
+        self.write_offset(o, m)?;
+        if self.pretty {
+            write!(o, "{}\t", style(pattern).cyan())?;
+        } else {
+            write!(o, "{pattern}\t")?;
+        }
+        self.write_bytes(o, m)
+    }
+
+    fn write_offset(&self, o: &mut dyn Write, m: &Match) -> std::io::Result<()> {
+        if self.pretty {
+            write!(o, "{:08x}\t", style(m.start()).green())
+        } else {
+            write!(o, "{:08x}\t", m.start())
+        }
+    }
+
+    fn write_bytes(&self, o: &mut dyn Write, m: &Match) -> std::io::Result<()> {
+        for (i, (b, highlight)) in m.bytes().iter().zip(m.highlight()).enumerate() {
+            if self.space != 0 && i != 0 && (i as u32).is_multiple_of(self.space) {
+                write!(o, " ")?;
+            }
+
+            if self.pretty {
+                if !highlight {
+                    write!(o, "{:02x}", style(b))?;
+                } else {
+                    write!(o, "{:02x}", style(b).red())?;
+                }
+            } else {
+                write!(o, "{:02x}", b)?;
+            }
+        }
+
+        // captures follow the bytes as name=value
+        for capture in m.captures() {
+            let value = capture.format().format(m.capture_bytes(capture));
+            if self.pretty {
+                write!(o, "\t{}={value}", style(capture.name()).yellow())?;
+            } else {
+                write!(o, "\t{}={value}", capture.name())?;
+            }
+        }
+        writeln!(o)
+    }
+
+    // a match as a single line of json
+    fn write_json(
+        &self,
+        o: &mut dyn Write,
+        name: &str,
+        pattern: Option<&str>,
+        m: &Match,
+    ) -> std::io::Result<()> {
+        write!(o, "{{\"file\":{},", json_string(name))?;
+        if let Some(pattern) = pattern {
+            write!(o, "\"pattern\":{},", json_string(pattern))?;
+        }
+        write!(
+            o,
+            "\"offset\":{},\"bytes\":\"{}\",\"captures\":[",
+            m.start(),
+            CaptureFormat::Hex.format(m.bytes())
+        )?;
+
+        for (i, capture) in m.captures().iter().enumerate() {
+            if i != 0 {
+                write!(o, ",")?;
+            }
+            let bytes = m.capture_bytes(capture);
+            let value = match capture.format() {
+                CaptureFormat::Int(ty) => ty.decode(bytes).to_string(),
+                CaptureFormat::Str => json_string(&String::from_utf8_lossy(bytes)),
+                format => json_string(&format.format(bytes)),
+            };
+            write!(
+                o,
+                "{{\"name\":{},\"offset\":{},\"len\":{},\"value\":{value}}}",
+                json_string(capture.name()),
+                m.start() + capture.start(),
+                capture.end() - capture.start()
+            )?;
+        }
+        writeln!(o, "]}}")
+    }
+}
+
+// a quoted and escaped json string
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
 }
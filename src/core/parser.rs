@@ -1,6 +1,11 @@
+use crate::core::error::{Error, SyntaxError};
+
 pub struct Parser {
     pub src: String,
     pub pos: usize,
+    chars: Vec<char>,
+    // a line ended since the last char was taken
+    newline: bool,
 }
 
 impl Parser {
@@ -8,17 +13,57 @@ impl Parser {
         Self {
             src: src.into(),
             pos: 0,
+            chars: src.chars().collect(),
+            newline: false,
         }
     }
 
+    // skip whitespace and # comments
     pub fn trim(&mut self) {
-        while self.peek().is_whitespace() {
-            self.pos += 1;
+        loop {
+            while self.peek().is_whitespace() {
+                self.newline |= self.peek() == '\n';
+                self.pos += 1;
+            }
+
+            if self.peek() != '#' {
+                break;
+            }
+            while !self.is_end() && self.peek() != '\n' {
+                self.pos += 1;
+            }
+        }
+    }
+
+    // src without a trailing # comment
+    // a # inside of a string does not start one
+    pub fn strip_comment(src: &str) -> &str {
+        let mut string = false;
+        let mut escaped = false;
+        for (i, c) in src.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if string => escaped = true,
+                '"' => string = !string,
+                '#' if !string => return &src[..i],
+                _ => {}
+            }
         }
+        src
+    }
+
+    // true if a line ended after the last char that was taken
+    pub fn newline(&self) -> bool {
+        self.newline
     }
 
     pub fn peek(&self) -> char {
-        self.src.chars().nth(self.pos).unwrap_or('\0')
+        self.peek_at(0)
+    }
+
+    // look n chars ahead without trimming
+    pub fn peek_at(&self, n: usize) -> char {
+        self.chars.get(self.pos + n).copied().unwrap_or('\0')
     }
 
     pub fn peek_trim(&mut self) -> char {
@@ -41,20 +86,26 @@ impl Parser {
     }
 
     pub fn is_end(&self) -> bool {
-        self.pos >= self.src.len()
+        self.pos >= self.chars.len()
     }
 
     pub fn adv(&mut self) -> char {
         self.trim();
+        self.adv_raw()
+    }
+
+    // advance without skipping whitespace
+    pub fn adv_raw(&mut self) -> char {
         let c = self.peek();
 
         self.pos += 1;
+        self.newline = false;
         c
     }
 
     // get a slice of the input stream starting at pos
     // until the condition in f is false
-    pub fn until(&mut self, f: fn(x: char) -> bool) -> &str {
+    pub fn until(&mut self, f: fn(x: char) -> bool) -> String {
         let from = self.pos;
 
         loop {
@@ -64,7 +115,15 @@ impl Parser {
                 self.adv();
             }
         }
-        let to = self.pos;
-        &self.src[from..to]
+        self.chars[from..self.pos].iter().collect()
+    }
+
+    // a syntax error starting at start and ending at the current position
+    pub fn error(&self, start: usize, expected: &str) -> Error {
+        self.error_span(start, self.pos.max(start + 1), expected)
+    }
+
+    pub fn error_span(&self, start: usize, end: usize, expected: &str) -> Error {
+        Error::BadSyntax(SyntaxError::new(&self.src, start, end, expected))
     }
 }
@@ -0,0 +1,160 @@
+use memchr::memmem::Finder;
+
+use crate::core::{
+    error::RbrepResult,
+    expr::{Expr, ExprBranch, ExprKind},
+    input::MatchInput,
+};
+
+// A literal that every match contains at a fixed offset from its start.
+// Searching for it skips all offsets at which a match is impossible.
+#[derive(Clone)]
+pub struct Prefilter {
+    finder: Finder<'static>,
+    // offset of the literal from the start of a match
+    offset: usize,
+}
+
+impl Prefilter {
+    // None if no literal could be found
+    pub fn new(tree: &ExprBranch) -> Option<Self> {
+        let mut best: Option<(usize, Vec<u8>)> = None;
+        let mut run: Option<(usize, Vec<u8>)> = None;
+        let mut offset = 0;
+
+        let mut end_run = |run: &mut Option<(usize, Vec<u8>)>| {
+            if let Some(run) = run.take() {
+                if best.as_ref().is_none_or(|best| best.1.len() < run.1.len()) {
+                    best = Some(run);
+                }
+            }
+        };
+
+        for expr in tree {
+            if let Some(lit) = Self::literal(expr) {
+                offset += lit.len();
+                run.get_or_insert_with(|| (offset - lit.len(), vec![]))
+                    .1
+                    .extend(lit);
+                continue;
+            }
+
+            end_run(&mut run);
+            // everything after a variable length is not at a fixed offset
+            match expr.max_len() {
+                Some(len) if len == expr.min_len() => offset += len,
+                _ => break,
+            }
+        }
+        end_run(&mut run);
+
+        let (offset, needle) = best.filter(|(_, needle)| !needle.is_empty())?;
+        Some(Self {
+            finder: Finder::new(&needle).into_owned(),
+            offset,
+        })
+    }
+
+    // the exact bytes an expression matches if there is only one way to match it
+    fn literal(expr: &Expr) -> Option<Vec<u8>> {
+        if expr.max() != Some(expr.min()) {
+            return None;
+        }
+
+        let once = match expr.kind() {
+            ExprKind::Byte { value } => vec![*value],
+            ExprKind::String { value } => value.clone(),
+            ExprKind::Int { ty, value } => ty.encode(*value),
+            // only where the bytes are is checked
+            ExprKind::Start | ExprKind::End | ExprKind::Align { .. } => vec![],
+            ExprKind::Group { nodes, and: true } | ExprKind::Capture { nodes, .. } => nodes
+                .iter()
+                .map(Self::literal)
+                .collect::<Option<Vec<_>>>()?
+                .concat(),
+            ExprKind::Group { nodes, and: false } if nodes.len() == 1 => Self::literal(&nodes[0])?,
+            _ => return None,
+        };
+        Some(once.repeat(expr.min() as usize))
+    }
+
+    pub fn needle(&self) -> &[u8] {
+        self.finder.needle()
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    // Advance the input to the next offset at which a match may start.
+    // Returns false if there is no such offset in the rest of the input.
+    pub fn skip<IF: MatchInput>(&self, i: &mut IF) -> RbrepResult<bool> {
+        let len = self.offset + self.needle().len();
+        loop {
+            let window = i.window(len)?;
+            if window.len() < len {
+                return Ok(false);
+            }
+
+            if let Some(at) = self.finder.find(&window[self.offset..]) {
+                i.advance(at)?;
+                return Ok(true);
+            }
+
+            // the literal may still start in the last few bytes of the window
+            let skip = window.len() - len + 1;
+            i.advance(skip)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::input::FileBufferInput;
+
+    fn prefilter(expr: &str) -> Option<(usize, Vec<u8>)> {
+        Prefilter::new(&Expr::tree_from(expr).unwrap()).map(|p| (p.offset(), p.needle().to_vec()))
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(
+            Some((0, b"MZ".to_vec())),
+            prefilter("\"MZ\" ??{0,64} \"PE\"")
+        );
+        assert_eq!(
+            Some((0, b"\x7fELF".to_vec())),
+            prefilter("7f \"ELF\" (01 02)")
+        );
+        assert_eq!(
+            Some((3, b"0000".to_vec())),
+            prefilter("?? 31-33 ?? 30*4; ?? 31")
+        );
+        assert_eq!(Some((2, vec![0x34, 0x12])), prefilter("??{2} u16le:0x1234"));
+        assert_eq!(Some((0, b"0101".to_vec())), prefilter("&(30 31){2}"));
+        assert_eq!(
+            Some((0, b"MZ".to_vec())),
+            prefilter("\"MZ\" @len(u8) \"PE\"")
+        );
+        assert_eq!(
+            Some((0, b"MZPE".to_vec())),
+            prefilter("^ \"MZ\" @align(2) \"PE\"")
+        );
+        assert_eq!(None, prefilter("\"\" ??"));
+        assert_eq!(None, prefilter("?? 30{1,2}"));
+        assert_eq!(None, prefilter("??{0,2} 30"));
+    }
+
+    #[test]
+    fn skip() {
+        let prefilter = Prefilter::new(&Expr::tree_from("?? \"AB\"").unwrap()).unwrap();
+        let data = b"xxxxxxAByyAxxxxB";
+        let mut input = FileBufferInput::with_chunk_size(data.as_slice(), 4);
+
+        assert!(prefilter.skip(&mut input).unwrap());
+        assert_eq!(5, input.pos());
+        input.advance(1).unwrap();
+        assert!(!prefilter.skip(&mut input).unwrap());
+    }
+}
@@ -0,0 +1,900 @@
+use std::collections::HashSet;
+
+use crate::core::{
+    error::{Error, RbrepResult},
+    expr::{Expr, ExprBranch, ExprKind},
+    input::MatchInput,
+    int::IntType,
+    output::{Capture, CaptureFormat, ExprOutData, ExprOutput, MatchOutput},
+};
+
+// A single instruction of a compiled expression.
+// Every instruction that consumes input reads at the current position
+// and either advances past what it matched or fails the current path.
+#[derive(Clone, Debug)]
+pub enum Inst {
+    Byte(u8),
+    And(u8),
+    Mask {
+        mask: u8,
+        value: u8,
+    },
+    Range {
+        from: u8,
+        to: u8,
+        mask: u8,
+    },
+    // any byte, not highlighted
+    Any,
+    // consumes a single byte if the program does not match here
+    Not(Box<Program>),
+    Literal(Vec<u8>),
+    // an integer in from..=to
+    Int {
+        ty: IntType,
+        from: i128,
+        to: i128,
+    },
+    // continue at the first target, the second is tried when backtracking
+    Split(usize, usize),
+    Jmp(usize),
+    // remember the current position in a capture slot
+    // slot 2n is the start of capture n and 2n + 1 its end
+    Save(usize),
+    // the start of the input
+    Start,
+    // the end of the input
+    End,
+    // an offset that is a multiple of n
+    Align(usize),
+    // the bytes capture n currently holds
+    Backref(usize),
+    // read a length and remember where the bytes it counts end in a register
+    ReadLen {
+        ty: IntType,
+        register: usize,
+    },
+    // consume every byte up to the end in a register, not highlighted
+    Skip(usize),
+    // nothing may be consumed past the end in a register
+    // until the matching check_end
+    Bound(usize),
+    // never matches unless the end in a register is reached
+    CheckEnd(usize),
+    // matches without consuming anything if the program matches
+    // at the offset capture n holds
+    At {
+        prog: Box<Program>,
+        capture: usize,
+        ty: IntType,
+        relative: bool,
+    },
+    // never matches
+    Fail,
+    Match,
+}
+
+// A compiled expression that is executed by a backtracking vm.
+// Repetitions are unrolled so that the current position and
+// instruction are all the state a path has.
+#[derive(Clone, Debug, Default)]
+pub struct Program {
+    insts: Vec<Inst>,
+    // name and format of every capture
+    captures: Vec<(String, CaptureFormat)>,
+    // capture slots followed by registers
+    slots: usize,
+    // the register that holds how far the innermost length goes
+    limit: Option<usize>,
+    // what a path matches depends on more than its position
+    // if captures or lengths are read while matching
+    stateful: bool,
+}
+
+// the most bytes a length may skip or an offset may look ahead
+// every byte up to there has to be kept in memory,
+// larger lengths and offsets never match
+pub const MAX_SKIP: usize = 16 * 1024 * 1024;
+
+// an entry of the backtracking stack
+enum Frame {
+    // continue at pc and pos
+    Retry(usize, usize),
+    // undo a save when backtracking past it
+    Restore(usize, Option<usize>),
+}
+
+impl Program {
+    pub fn compile(tree: &ExprBranch) -> Self {
+        Self::compile_with(tree, vec![])
+    }
+
+    // captures that are already known keep their index
+    // so that a nested program can see the captures of its parent
+    fn compile_with(tree: &ExprBranch, mut captures: Vec<(String, CaptureFormat)>) -> Self {
+        // every capture is known up front because a backreference may come first
+        let mut stateful = false;
+        Self::collect(tree, &mut captures, &mut stateful);
+
+        let mut prog = Self {
+            insts: vec![],
+            slots: captures.len() * 2,
+            limit: None,
+            captures,
+            stateful,
+        };
+        prog.branch(tree);
+        prog.insts.push(Inst::Match);
+        prog
+    }
+
+    // find every capture and everything that reads them in a tree
+    fn collect(tree: &[Expr], captures: &mut Vec<(String, CaptureFormat)>, stateful: &mut bool) {
+        for expr in tree {
+            match expr.kind() {
+                ExprKind::Capture { name, format, .. }
+                    if !captures.iter().any(|(n, _)| n == name) =>
+                {
+                    captures.push((name.clone(), *format));
+                }
+                ExprKind::Backref { .. } | ExprKind::Len { .. } | ExprKind::At { .. } => {
+                    *stateful = true
+                }
+                _ => {}
+            }
+            Self::collect(expr.children(), captures, stateful);
+        }
+    }
+
+    // a slot that is not a capture
+    fn register(&mut self) -> usize {
+        self.slots += 1;
+        self.slots - 1
+    }
+
+    // the index of a capture
+    fn capture_index(&self, name: &str) -> usize {
+        self.captures
+            .iter()
+            .position(|(n, _)| n == name)
+            .expect("captures are collected before compiling")
+    }
+
+    pub fn insts(&self) -> &[Inst] {
+        &self.insts
+    }
+
+    fn branch(&mut self, tree: &ExprBranch) {
+        for expr in tree {
+            self.expr(expr);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        for _ in 0..expr.min() {
+            self.kind(expr.kind());
+        }
+
+        match expr.max() {
+            // L: split body, exit
+            //    body
+            //    jmp L
+            None => {
+                let split = self.placeholder();
+                self.kind(expr.kind());
+                self.insts.push(Inst::Jmp(split));
+                self.patch(split, split + 1, self.insts.len(), expr.lazy());
+            }
+            // every optional copy may end the repetition
+            Some(max) => {
+                let mut splits = vec![];
+                for _ in expr.min()..max {
+                    splits.push(self.placeholder());
+                    self.kind(expr.kind());
+                }
+                let exit = self.insts.len();
+                for split in splits {
+                    self.patch(split, split + 1, exit, expr.lazy());
+                }
+            }
+        }
+    }
+
+    fn kind(&mut self, kind: &ExprKind) {
+        let inst = match kind {
+            ExprKind::Byte { value } => Inst::Byte(*value),
+            ExprKind::And { value } => Inst::And(*value),
+            ExprKind::Not { expr } => Inst::Not(Box::new(Self::compile_with(
+                &vec![expr.as_ref().clone()],
+                self.captures.clone(),
+            ))),
+            ExprKind::Any => Inst::Any,
+            ExprKind::Group { nodes, and: true } => return self.branch(nodes),
+            ExprKind::Group { nodes, and: false } => return self.alternatives(nodes),
+            ExprKind::Capture { name, nodes, .. } => return self.capture(name, nodes),
+            ExprKind::Start => Inst::Start,
+            ExprKind::End => Inst::End,
+            ExprKind::Align { align } => Inst::Align(*align),
+            ExprKind::Backref { name, .. } => Inst::Backref(self.capture_index(name)),
+            ExprKind::Len { ty, nodes } => return self.len(*ty, nodes.as_ref()),
+            ExprKind::At {
+                name,
+                relative,
+                nodes,
+                ..
+            } => {
+                let capture = self.capture_index(name);
+                let CaptureFormat::Int(ty) = self.captures[capture].1 else {
+                    unreachable!("offsets are checked by the parser")
+                };
+                Inst::At {
+                    prog: Box::new(Self::compile_with(nodes, self.captures.clone())),
+                    capture,
+                    ty,
+                    relative: *relative,
+                }
+            }
+            ExprKind::String { value } => Inst::Literal(value.clone()),
+            ExprKind::Mask { mask, value } => Inst::Mask {
+                mask: *mask,
+                value: *value,
+            },
+            ExprKind::Range { from, to, mask } => Inst::Range {
+                from: *from,
+                to: *to,
+                mask: *mask,
+            },
+            // an exact integer is just its bytes
+            ExprKind::Int { ty, value } => Inst::Literal(ty.encode(*value)),
+            ExprKind::IntRange { ty, from, to } => Inst::Int {
+                ty: *ty,
+                from: *from,
+                to: *to,
+            },
+            ExprKind::Ref { .. } => unreachable!("references are resolved by the parser"),
+        };
+        self.insts.push(inst);
+    }
+
+    // split a, L1
+    // a
+    // jmp exit
+    // L1: split b, L2
+    // ...
+    fn alternatives(&mut self, nodes: &[Expr]) {
+        let mut jmps = vec![];
+        for (i, node) in nodes.iter().enumerate() {
+            if i == nodes.len() - 1 {
+                self.expr(node);
+                break;
+            }
+
+            let split = self.placeholder();
+            self.expr(node);
+            jmps.push(self.placeholder());
+            self.patch(split, split + 1, self.insts.len(), false);
+        }
+
+        // an empty group never matches
+        if nodes.is_empty() {
+            self.insts.push(Inst::Fail);
+        }
+
+        let exit = self.insts.len();
+        for jmp in jmps {
+            self.insts[jmp] = Inst::Jmp(exit);
+        }
+    }
+
+    // captures with the same name share their slots
+    // the last one that matched wins
+    fn capture(&mut self, name: &str, nodes: &ExprBranch) {
+        let index = self.capture_index(name);
+        self.insts.push(Inst::Save(index * 2));
+        self.branch(nodes);
+        self.insts.push(Inst::Save(index * 2 + 1));
+    }
+
+    // read_len r
+    // skip r
+    // or
+    // read_len r
+    // bound r
+    // nodes
+    // check_end r
+    fn len(&mut self, ty: IntType, nodes: Option<&ExprBranch>) {
+        if nodes.is_some() && self.limit.is_none() {
+            self.limit = Some(self.register());
+        }
+
+        let register = self.register();
+        self.insts.push(Inst::ReadLen { ty, register });
+        let Some(nodes) = nodes else {
+            self.insts.push(Inst::Skip(register));
+            return;
+        };
+
+        // the next register holds the limit that was active before
+        self.register();
+        self.insts.push(Inst::Bound(register));
+        self.branch(nodes);
+        self.insts.push(Inst::CheckEnd(register));
+    }
+
+    fn placeholder(&mut self) -> usize {
+        self.insts.push(Inst::Jmp(usize::MAX));
+        self.insts.len() - 1
+    }
+
+    // a lazy split prefers to skip
+    fn patch(&mut self, at: usize, take: usize, skip: usize, lazy: bool) {
+        self.insts[at] = if lazy {
+            Inst::Split(skip, take)
+        } else {
+            Inst::Split(take, skip)
+        };
+    }
+
+    // Run the program anchored at the current position of the input.
+    // The first path that reaches a match wins, every byte it consumed is pushed to res.
+    // An empty match is not a match unless allow_empty is set.
+    pub fn exec<IF, OF>(&self, i: &mut IF, res: &mut OF, allow_empty: bool) -> RbrepResult<bool>
+    where
+        IF: MatchInput,
+        OF: MatchOutput,
+    {
+        let mut slots = vec![];
+        if !self.exec_at(i, 0, res, allow_empty, &mut slots)? {
+            return Ok(false);
+        }
+        self.captures(&slots, res);
+        Ok(true)
+    }
+
+    // base is the offset the program starts at
+    // slots start out as the captures of the program this one is nested in
+    // and hold the captures of the match once one is found
+    fn exec_at<IF, OF>(
+        &self,
+        i: &mut IF,
+        base: usize,
+        res: &mut OF,
+        allow_empty: bool,
+        slots: &mut Vec<Option<usize>>,
+    ) -> RbrepResult<bool>
+    where
+        IF: MatchInput,
+        OF: MatchOutput,
+    {
+        // paths to try when the current one fails
+        let mut stack: Vec<Frame> = vec![];
+        // a split that is reached again at the same position with the same slots
+        // can not do any better than it did the first time,
+        // the slots only matter if a later instruction reads them
+        let mut visited: HashSet<(usize, usize, Vec<Option<usize>>)> = HashSet::new();
+
+        // offsets of the captures relative to the input position
+        // registers of a parent mean nothing here
+        slots.truncate(self.captures.len() * 2);
+        slots.resize(self.slots, None);
+        let limit = self.limit.unwrap_or(usize::MAX);
+
+        let start = res.len();
+        let mut pc = 0;
+        let mut pos = 0;
+        loop {
+            let next = match &self.insts[pc] {
+                Inst::Split(a, b) => {
+                    let state = if self.stateful { slots.clone() } else { vec![] };
+                    if visited.insert((pc, pos, state)) {
+                        stack.push(Frame::Retry(*b, pos));
+                        Some(*a)
+                    } else {
+                        None
+                    }
+                }
+                Inst::Jmp(to) => Some(*to),
+                Inst::Save(slot) => {
+                    Self::set(&mut stack, slots, *slot, Some(base + pos));
+                    Some(pc + 1)
+                }
+                Inst::ReadLen { ty, register } => match Self::read_len(ty, i, base + pos, res)? {
+                    // a length may not go past the one it is part of
+                    Some(end) if slots.get(limit).copied().flatten().is_none_or(|l| end <= l) => {
+                        Self::set(&mut stack, slots, *register, Some(end));
+                        pos += ty.size;
+                        Some(pc + 1)
+                    }
+                    _ => None,
+                },
+                Inst::Bound(register) => {
+                    let (outer, end) = (slots[limit], slots[*register]);
+                    Self::set(&mut stack, slots, register + 1, outer);
+                    Self::set(&mut stack, slots, limit, end);
+                    Some(pc + 1)
+                }
+                Inst::CheckEnd(register) => {
+                    if slots[*register] == Some(base + pos) {
+                        let outer = slots[register + 1];
+                        Self::set(&mut stack, slots, limit, outer);
+                        Some(pc + 1)
+                    } else {
+                        None
+                    }
+                }
+                Inst::At {
+                    prog,
+                    capture,
+                    ty,
+                    relative,
+                } => {
+                    let capture = (slots[capture * 2], slots[capture * 2 + 1]);
+                    Self::at(prog, capture, ty, *relative, i, slots)?.then_some(pc + 1)
+                }
+                Inst::Start => (i.pos() + base + pos == 0).then_some(pc + 1),
+                Inst::End => match i.read(base + pos) {
+                    Err(Error::EndOfFile) => Some(pc + 1),
+                    Err(err) => return Err(err),
+                    Ok(_) => None,
+                },
+                Inst::Align(align) => (i.pos() + base + pos)
+                    .is_multiple_of(*align)
+                    .then_some(pc + 1),
+                Inst::Fail => None,
+                Inst::Match => {
+                    if pos != 0 || allow_empty {
+                        return Ok(true);
+                    }
+                    None
+                }
+                inst => {
+                    let len = Self::step(inst, i, base + pos, res, slots)?;
+                    pos += len.unwrap_or(0);
+                    // a path that went past the end of a length can never reach it again
+                    let bounded = slots.get(limit).copied().flatten();
+                    len.filter(|_| bounded.is_none_or(|l| base + pos <= l))
+                        .map(|_| pc + 1)
+                }
+            };
+
+            match next {
+                Some(next) => pc = next,
+                None => loop {
+                    match stack.pop() {
+                        Some(Frame::Retry(next, at)) => {
+                            pc = next;
+                            pos = at;
+                            res.truncate(start + pos);
+                            break;
+                        }
+                        Some(Frame::Restore(slot, old)) => slots[slot] = old,
+                        None => return Ok(false),
+                    }
+                },
+            }
+        }
+    }
+
+    // change a slot so that backtracking past here restores it
+    fn set(stack: &mut Vec<Frame>, slots: &mut [Option<usize>], slot: usize, value: Option<usize>) {
+        stack.push(Frame::Restore(slot, slots[slot]));
+        slots[slot] = value;
+    }
+
+    // add every capture that took part in a match
+    fn captures<OF: MatchOutput>(&self, slots: &[Option<usize>], res: &mut OF) {
+        let mut captures: Vec<Capture> = self
+            .captures
+            .iter()
+            .enumerate()
+            .filter_map(
+                |(i, (name, format))| match (slots[i * 2], slots[i * 2 + 1]) {
+                    (Some(start), Some(end)) => Some(Capture::new(name, *format, start, end)),
+                    _ => None,
+                },
+            )
+            .collect();
+        captures.sort_by_key(|c| c.start());
+        for capture in captures {
+            res.capture(capture);
+        }
+    }
+
+    // apply a consuming instruction at offset
+    // returns how many bytes it consumed or None if it did not match
+    fn step<IF, OF>(
+        inst: &Inst,
+        i: &mut IF,
+        offset: usize,
+        res: &mut OF,
+        slots: &[Option<usize>],
+    ) -> RbrepResult<Option<usize>>
+    where
+        IF: MatchInput,
+        OF: MatchOutput,
+    {
+        // these may match without reading anything
+        match inst {
+            Inst::Backref(index) => {
+                return Self::backref(slots[index * 2], slots[index * 2 + 1], i, offset, res)
+            }
+            Inst::Skip(register) => return Self::skip(slots[*register], i, offset, res),
+            _ => {}
+        }
+
+        // running out of input simply means there is no match
+        let first = match i.read(offset) {
+            Err(Error::EndOfFile) => return Ok(None),
+            first => first?,
+        };
+
+        let matched = match inst {
+            Inst::Byte(value) => first == *value,
+            Inst::And(value) => first & value != 0,
+            Inst::Mask { mask, value } => first & mask == *value,
+            Inst::Range { from, to, mask } => (*from..*to).contains(&(first & mask)),
+            Inst::Any => {
+                res.push(ExprOutData::new(first, false));
+                return Ok(Some(1));
+            }
+            Inst::Not(prog) => {
+                !prog.exec_at(i, offset, &mut OF::default(), true, &mut slots.to_vec())?
+            }
+            Inst::Literal(value) => return Self::literal(value, i, offset, res),
+            Inst::Int { ty, from, to } => return Self::int(ty, *from..=*to, i, offset, res),
+            Inst::Split(..)
+            | Inst::Jmp(_)
+            | Inst::Save(_)
+            | Inst::Start
+            | Inst::End
+            | Inst::Align(_)
+            | Inst::Backref(_)
+            | Inst::ReadLen { .. }
+            | Inst::Skip(_)
+            | Inst::Bound(_)
+            | Inst::CheckEnd(_)
+            | Inst::At { .. }
+            | Inst::Fail
+            | Inst::Match => {
+                unreachable!()
+            }
+        };
+
+        if !matched {
+            return Ok(None);
+        }
+        res.push(ExprOutData::new(first, true));
+        Ok(Some(1))
+    }
+
+    fn literal<IF, OF>(
+        value: &[u8],
+        i: &mut IF,
+        offset: usize,
+        res: &mut OF,
+    ) -> RbrepResult<Option<usize>>
+    where
+        IF: MatchInput,
+        OF: MatchOutput,
+    {
+        for (idx, b) in value.iter().enumerate() {
+            let next = match i.read(offset + idx) {
+                Err(Error::EndOfFile) => return Ok(None),
+                next => next?,
+            };
+            if next != *b {
+                return Ok(None);
+            }
+        }
+
+        for b in value {
+            res.push(ExprOutData::new(*b, true));
+        }
+        Ok(Some(value.len()))
+    }
+
+    // the bytes between start and end have to appear again at offset
+    // a capture that did not match yet never matches
+    fn backref<IF, OF>(
+        start: Option<usize>,
+        end: Option<usize>,
+        i: &mut IF,
+        offset: usize,
+        res: &mut OF,
+    ) -> RbrepResult<Option<usize>>
+    where
+        IF: MatchInput,
+        OF: MatchOutput,
+    {
+        let (Some(start), Some(end)) = (start, end) else {
+            return Ok(None);
+        };
+        // a capture that is referenced inside of itself is not finished yet
+        if end < start {
+            return Ok(None);
+        }
+
+        let mut bytes = Vec::with_capacity(end - start);
+        for idx in 0..end - start {
+            let expected = i.read(start + idx)?;
+            let next = match i.read(offset + idx) {
+                Err(Error::EndOfFile) => return Ok(None),
+                next => next?,
+            };
+            if next != expected {
+                return Ok(None);
+            }
+            bytes.push(next);
+        }
+
+        for b in &bytes {
+            res.push(ExprOutData::new(*b, true));
+        }
+        Ok(Some(bytes.len()))
+    }
+
+    // the bytes of an integer at offset
+    // None if the input ends before
+    fn read_int<IF: MatchInput>(
+        ty: &IntType,
+        i: &mut IF,
+        offset: usize,
+    ) -> RbrepResult<Option<[u8; 8]>> {
+        let mut bytes = [0; 8];
+        for (idx, b) in bytes.iter_mut().take(ty.size).enumerate() {
+            *b = match i.read(offset + idx) {
+                Err(Error::EndOfFile) => return Ok(None),
+                next => next?,
+            };
+        }
+        Ok(Some(bytes))
+    }
+
+    // decode an integer at offset
+    // and push its bytes if it is in range
+    fn int<IF, OF>(
+        ty: &IntType,
+        range: std::ops::RangeInclusive<i128>,
+        i: &mut IF,
+        offset: usize,
+        res: &mut OF,
+    ) -> RbrepResult<Option<usize>>
+    where
+        IF: MatchInput,
+        OF: MatchOutput,
+    {
+        let Some(bytes) = Self::read_int(ty, i, offset)? else {
+            return Ok(None);
+        };
+
+        if !range.contains(&ty.decode(&bytes)) {
+            return Ok(None);
+        }
+        for b in &bytes[..ty.size] {
+            res.push(ExprOutData::new(*b, true));
+        }
+        Ok(Some(ty.size))
+    }
+
+    // read a length at offset and push its bytes
+    // returns the offset one past the bytes it counts
+    fn read_len<IF, OF>(
+        ty: &IntType,
+        i: &mut IF,
+        offset: usize,
+        res: &mut OF,
+    ) -> RbrepResult<Option<usize>>
+    where
+        IF: MatchInput,
+        OF: MatchOutput,
+    {
+        let Some(bytes) = Self::read_int(ty, i, offset)? else {
+            return Ok(None);
+        };
+        // a negative length never matches
+        let Ok(len) = usize::try_from(ty.decode(&bytes)) else {
+            return Ok(None);
+        };
+        if len > MAX_SKIP {
+            return Ok(None);
+        }
+
+        for b in &bytes[..ty.size] {
+            res.push(ExprOutData::new(*b, true));
+        }
+        Ok((offset + ty.size).checked_add(len))
+    }
+
+    // consume everything from offset up to end
+    fn skip<IF, OF>(
+        end: Option<usize>,
+        i: &mut IF,
+        offset: usize,
+        res: &mut OF,
+    ) -> RbrepResult<Option<usize>>
+    where
+        IF: MatchInput,
+        OF: MatchOutput,
+    {
+        let Some(end) = end.filter(|end| end.saturating_sub(offset) <= MAX_SKIP) else {
+            return Ok(None);
+        };
+
+        // a length past the end of the input is common when the length is garbage,
+        // only the last byte has to be looked at to find out
+        if end > offset {
+            match i.read(end - 1) {
+                Err(Error::EndOfFile) => return Ok(None),
+                b => b?,
+            };
+        }
+
+        for b in &i.window(end)?[offset..end] {
+            res.push(ExprOutData::new(*b, false));
+        }
+        Ok(Some(end - offset))
+    }
+
+    // run prog at the offset a capture holds
+    // an offset before the current position is read through the input,
+    // which fails if a streamed input already dropped it
+    fn at<IF: MatchInput>(
+        prog: &Program,
+        capture: (Option<usize>, Option<usize>),
+        ty: &IntType,
+        relative: bool,
+        i: &mut IF,
+        slots: &[Option<usize>],
+    ) -> RbrepResult<bool> {
+        let (Some(start), Some(_)) = capture else {
+            return Ok(false);
+        };
+        let Some(bytes) = Self::read_int(ty, i, start)? else {
+            return Ok(false);
+        };
+
+        let mut target = ty.decode(&bytes);
+        if relative {
+            target += i.pos() as i128;
+        }
+        let Ok(target) = usize::try_from(target) else {
+            return Ok(false);
+        };
+
+        let mut res = ExprOutput::default();
+        let mut slots = slots.to_vec();
+        match target.checked_sub(i.pos()) {
+            Some(base) if base <= MAX_SKIP => prog.exec_at(i, base, &mut res, true, &mut slots),
+            Some(_) => Ok(false),
+            None => {
+                // captures of the parent are relative to its position
+                let behind = i.pos() - target;
+                slots.iter_mut().flatten().for_each(|slot| *slot += behind);
+                let mut seek = Seek {
+                    input: i,
+                    pos: target,
+                };
+                // bytes a stream already dropped can not match
+                match prog.exec_at(&mut seek, 0, &mut res, true, &mut slots) {
+                    Err(Error::Unbuffered(_)) => Ok(false),
+                    found => found,
+                }
+            }
+        }
+    }
+}
+
+// The input seen from another offset.
+// Used to run a program before the current position of the input.
+struct Seek<'a> {
+    input: &'a mut dyn MatchInput,
+    pos: usize,
+}
+
+impl MatchInput for Seek<'_> {
+    fn read(&mut self, offset: usize) -> RbrepResult<u8> {
+        self.input.read_at(self.pos + offset)
+    }
+
+    fn window(&mut self, len: usize) -> RbrepResult<&[u8]> {
+        self.input.window_at(self.pos, len)
+    }
+
+    fn window_at(&mut self, offset: usize, len: usize) -> RbrepResult<&[u8]> {
+        self.input.window_at(offset, len)
+    }
+
+    fn pos(&self) -> usize {
+        self.pos
+    }
+
+    // a program never asks, it reads until the end of the file
+    fn eof(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::core::input::FileBufferInput;
+
+    #[test]
+    fn compile() {
+        let prog = Program::compile(&Expr::tree_from("30{1,2} (31 32)").unwrap());
+        let insts: Vec<String> = prog.insts().iter().map(|i| format!("{i:?}")).collect();
+        assert_eq!(
+            vec![
+                "Byte(48)",
+                "Split(2, 3)",
+                "Byte(48)",
+                "Split(4, 6)",
+                "Byte(49)",
+                "Jmp(7)",
+                "Byte(50)",
+                "Match",
+            ],
+            insts
+        );
+
+        let prog = Program::compile(&Expr::tree_from("??{0,}? 30").unwrap());
+        assert!(matches!(prog.insts()[0], Inst::Split(3, 1)));
+        assert!(matches!(prog.insts()[2], Inst::Jmp(0)));
+    }
+
+    #[test]
+    fn max_skip() {
+        let prog = Program::compile(&Expr::tree_from("@len(u32le)").unwrap());
+        let run = |len: usize| {
+            let mut input = (len as u32).to_le_bytes().to_vec();
+            input.resize(len + 4, 0);
+            let mut res = ExprOutput::default();
+            let found = prog
+                .exec(&mut FileBufferInput::new(input.as_slice()), &mut res, false)
+                .unwrap();
+            found.then_some(res.len())
+        };
+        assert_eq!(Some(MAX_SKIP + 4), run(MAX_SKIP));
+        assert_eq!(None, run(MAX_SKIP + 1));
+    }
+
+    // counts the bytes read from the input
+    struct Counted<R> {
+        read: R,
+        count: usize,
+    }
+
+    impl<R: std::io::Read> std::io::Read for Counted<R> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = self.read.read(buf)?;
+            self.count += n;
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn at_stays_buffered() {
+        // a target or length far ahead of an endless stream
+        for expr in ["{p as u32le: ??*4;} @at(p, 30)", "@len(u32le, 30)"] {
+            let prog = Program::compile(&Expr::tree_from(expr).unwrap());
+            let mut read = Counted {
+                read: std::io::Read::chain(&[0xf0, 0xff, 0xff, 0xff][..], std::io::repeat(0)),
+                count: 0,
+            };
+            let mut input = FileBufferInput::with_chunk_size(&mut read, 16);
+            let mut res = ExprOutput::default();
+            assert!(!prog.exec(&mut input, &mut res, false).unwrap());
+            assert!(read.count <= 64, "{expr} read {} bytes", read.count);
+        }
+
+        // a target behind the bytes that are still buffered
+        let prog = Program::compile(&Expr::tree_from("{p as u32le: ??*4;} @at(p, 00)").unwrap());
+        let data = vec![0u8; 256];
+        let mut input = FileBufferInput::with_chunk_size(data.as_slice(), 4);
+        for _ in 0..128 {
+            input.advance(1).unwrap();
+        }
+        let mut res = ExprOutput::default();
+        assert!(!prog.exec(&mut input, &mut res, false).unwrap());
+    }
+}
@@ -0,0 +1,137 @@
+use std::{collections::VecDeque, io::Write};
+
+use crate::core::{
+    error::RbrepResult,
+    input::MatchInput,
+    options::{OutputOptions, SearchOptions},
+};
+
+// Finds the matches of one or more patterns at the offsets of an input.
+// The search loop itself is shared by every searcher, see Search.
+pub trait Searcher {
+    type Item;
+
+    // move the input to the next offset at which a match may start
+    // returns None if there is none
+    fn next_start<IF: MatchInput>(&mut self, input: &mut IF) -> RbrepResult<Option<usize>>;
+
+    // add every match that starts at the current position of the input
+    fn matches_here<IF: MatchInput>(
+        &mut self,
+        input: &mut IF,
+        found: &mut VecDeque<Self::Item>,
+    ) -> RbrepResult<()>;
+}
+
+// Iterator over every match a searcher finds in an input.
+// Matches may overlap, every offset of the input is a possible start.
+pub struct Search<IF: MatchInput, S: Searcher> {
+    searcher: S,
+    input: IF,
+    done: bool,
+    // matches at the current offset that were not returned yet
+    found: VecDeque<S::Item>,
+    // matches only start at multiples of this
+    align: usize,
+}
+
+impl<IF: MatchInput, S: Searcher> Search<IF, S> {
+    pub fn new(searcher: S, input: IF) -> Self {
+        Self {
+            searcher,
+            input,
+            done: false,
+            found: VecDeque::new(),
+            align: 1,
+        }
+    }
+
+    // only find matches that start at a multiple of align
+    pub fn aligned(mut self, align: usize) -> Self {
+        self.align = align.max(1);
+        self
+    }
+
+    // Write every match and return how many were found.
+    // The name is written in front of the first match,
+    // or in front of the count if only the count is written.
+    pub fn write_matches<F>(
+        self,
+        o: &mut dyn Write,
+        name: &str,
+        search: &SearchOptions,
+        output: &OutputOptions,
+        mut write_match: F,
+    ) -> anyhow::Result<usize>
+    where
+        F: FnMut(&mut dyn Write, S::Item) -> std::io::Result<()>,
+    {
+        let mut matches = 0;
+
+        for m in self
+            .aligned(search.align.unwrap_or(1))
+            .take(search.stop_after.unwrap_or(usize::MAX))
+        {
+            let m = m?;
+            matches += 1;
+            if output.quiet {
+                continue;
+            }
+
+            if matches == 1 && !output.count {
+                output.write_name(o, name)?;
+            }
+
+            // print current buffer if match
+            // and count is not set
+            if !output.count {
+                write_match(o, m)?;
+            }
+        }
+
+        if output.count && !output.quiet {
+            output.write_name(o, name)?;
+            output.write_count(o, name, matches)?;
+        }
+
+        Ok(matches)
+    }
+
+    fn next_match(&mut self) -> RbrepResult<Option<S::Item>> {
+        loop {
+            if let Some(found) = self.found.pop_front() {
+                return Ok(Some(found));
+            }
+
+            let Some(start) = self.searcher.next_start(&mut self.input)? else {
+                return Ok(None);
+            };
+            if !start.is_multiple_of(self.align) {
+                self.input.advance(self.align - start % self.align)?;
+                continue;
+            }
+
+            self.searcher
+                .matches_here(&mut self.input, &mut self.found)?;
+
+            // no matter what, we always advance a single byte
+            // to check all possible combinations
+            self.input.advance(1)?;
+        }
+    }
+}
+
+impl<IF: MatchInput, S: Searcher> Iterator for Search<IF, S> {
+    type Item = RbrepResult<S::Item>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let next = self.next_match().transpose();
+        // an error ends the iteration
+        self.done = !matches!(next, Some(Ok(_)));
+        next
+    }
+}
@@ -0,0 +1,494 @@
+use std::{
+    collections::{BTreeSet, VecDeque},
+    io::{Read, Write},
+};
+
+use aho_corasick::{AhoCorasick, Input};
+use anyhow::{bail, Context};
+
+use crate::core::{
+    dfa::DfaCache,
+    error::RbrepResult,
+    input::{FileBufferInput, MatchInput, DEFAULT_CHUNK_SIZE},
+    matcher::Matcher,
+    options::{OutputOptions, SearchOptions},
+    output::Match,
+    parser::Parser,
+    search::{Search, Searcher},
+};
+
+// a named expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    pub name: String,
+    pub expr: String,
+}
+
+impl Pattern {
+    pub fn new(name: &str, expr: &str) -> Self {
+        Self {
+            name: name.into(),
+            expr: expr.into(),
+        }
+    }
+
+    // Read every pattern in a pattern file.
+    // A pattern is declared as name = expr and continues until the next declaration.
+    // Lines before the first declaration are patterns on their own named after their expression,
+    // unless they are $name = definitions which are then shared by every pattern.
+    pub fn parse_list(src: &str) -> anyhow::Result<Vec<Self>> {
+        let mut patterns: Vec<Self> = vec![];
+        let mut current: Option<Self> = None;
+        let mut shared = String::new();
+
+        for line in src.lines() {
+            if let Some((name, expr)) = Self::declaration(line) {
+                if patterns.iter().chain(&current).any(|p| p.name == name) {
+                    bail!("pattern {name} is declared twice");
+                }
+                patterns.extend(current.take());
+                current = Some(Self::new(name, expr));
+            } else if let Some(current) = &mut current {
+                current.expr.push('\n');
+                current.expr.push_str(line);
+            } else {
+                let line = line.trim();
+                if line.strip_prefix('$').and_then(Self::declaration).is_some() {
+                    shared.push_str(line);
+                    shared.push('\n');
+                } else {
+                    let name = Parser::strip_comment(line).trim_end();
+                    if !name.is_empty() {
+                        patterns.push(Self::new(name, line));
+                    }
+                }
+            }
+        }
+        patterns.extend(current);
+
+        for pattern in &mut patterns {
+            pattern.expr.insert_str(0, &shared);
+        }
+        Ok(patterns)
+    }
+
+    // name = expr
+    fn declaration(line: &str) -> Option<(&str, &str)> {
+        let line = line.trim_start();
+        let end = line
+            .find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '-' | '.')))
+            .unwrap_or(line.len());
+        if end == 0 {
+            return None;
+        }
+
+        let (name, rest) = line.split_at(end);
+        let expr = rest.trim_start().strip_prefix('=')?;
+        Some((name, expr))
+    }
+}
+
+// Many patterns that are searched in a single pass over the input.
+// The literals of all patterns are found with aho-corasick,
+// each pattern is then only tried where its literal was found.
+// Patterns without a literal are tried at every offset.
+pub struct MatcherSet {
+    names: Vec<String>,
+    matchers: Vec<Matcher>,
+    literals: Option<AhoCorasick>,
+    // pattern and offset of the literal for every literal
+    owners: Vec<(usize, usize)>,
+    // patterns without a literal
+    always: Vec<usize>,
+    // longest literal and largest offset of any literal
+    max_literal: usize,
+    max_offset: usize,
+}
+
+impl MatcherSet {
+    pub fn new(patterns: &[Pattern]) -> anyhow::Result<Self> {
+        // errors only need to say which pattern is broken if there are many
+        let matchers = patterns
+            .iter()
+            .map(|p| match Matcher::new(&p.expr) {
+                Err(err) if patterns.len() > 1 => {
+                    Err(err).with_context(|| format!("pattern {}", p.name))
+                }
+                res => Ok(res?),
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut needles = vec![];
+        let mut owners = vec![];
+        let mut always = vec![];
+        for (i, matcher) in matchers.iter().enumerate() {
+            match matcher.prefilter() {
+                Some(prefilter) => {
+                    needles.push(prefilter.needle());
+                    owners.push((i, prefilter.offset()));
+                }
+                None => always.push(i),
+            }
+        }
+
+        let literals = if needles.is_empty() {
+            None
+        } else {
+            Some(AhoCorasick::new(&needles)?)
+        };
+
+        Ok(Self {
+            names: patterns.iter().map(|p| p.name.clone()).collect(),
+            max_literal: needles.iter().map(|n| n.len()).max().unwrap_or(0),
+            max_offset: owners.iter().map(|(_, offset)| *offset).max().unwrap_or(0),
+            matchers,
+            literals,
+            owners,
+            always,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.matchers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matchers.is_empty()
+    }
+
+    pub fn name(&self, pattern: usize) -> &str {
+        &self.names[pattern]
+    }
+
+    pub fn matchers(&self) -> &[Matcher] {
+        &self.matchers
+    }
+
+    // iterate over all matches of all patterns in a reader
+    pub fn find_iter<R: Read>(&self, reader: R) -> SetMatches<'_, FileBufferInput<R>> {
+        self.find_iter_input(FileBufferInput::new(reader))
+    }
+
+    pub fn find_iter_input<IF: MatchInput>(&self, input: IF) -> SetMatches<'_, IF> {
+        Search::new(
+            SetSearcher {
+                set: self,
+                caches: self
+                    .matchers
+                    .iter()
+                    .map(|m| m.dfa().map(DfaCache::new))
+                    .collect(),
+                candidates: BTreeSet::new(),
+                scanned: 0,
+                scanned_all: self.literals.is_none(),
+            },
+            input,
+        )
+    }
+
+    pub fn apply(
+        &self,
+        i: &mut dyn Read,
+        o: &mut dyn Write,
+        name: &str,
+        search: &SearchOptions,
+        output: &OutputOptions,
+    ) -> anyhow::Result<usize> {
+        self.apply_input(&mut FileBufferInput::new(i), o, name, search, output)
+    }
+
+    // a single pattern is printed exactly like a matcher would
+    // otherwise every match is tagged with the pattern that found it
+    pub fn apply_input<IF>(
+        &self,
+        input: &mut IF,
+        o: &mut dyn Write,
+        name: &str,
+        search: &SearchOptions,
+        output: &OutputOptions,
+    ) -> anyhow::Result<usize>
+    where
+        IF: MatchInput,
+    {
+        if let [matcher] = self.matchers.as_slice() {
+            return matcher.apply_input(input, o, name, search, output);
+        }
+
+        self.find_iter_input(input)
+            .write_matches(o, name, search, output, |o, (pattern, m)| {
+                output.write_pattern_match(o, name, self.name(pattern), &m)
+            })
+    }
+}
+
+// Iterator over every match of every pattern in a set.
+// Matches are ordered by offset and then by the order of the patterns.
+pub type SetMatches<'m, IF> = Search<IF, SetSearcher<'m>>;
+
+// Finds the matches of every pattern in a set.
+pub struct SetSearcher<'m> {
+    set: &'m MatcherSet,
+    caches: Vec<Option<DfaCache<'m>>>,
+    // offsets at which a pattern may start
+    candidates: BTreeSet<(usize, usize)>,
+    // every literal that starts before this offset was found
+    scanned: usize,
+    scanned_all: bool,
+}
+
+impl Searcher for SetSearcher<'_> {
+    type Item = (usize, Match);
+
+    fn next_start<IF: MatchInput>(&mut self, input: &mut IF) -> RbrepResult<Option<usize>> {
+        // candidates that were skipped can not match any more
+        let pos = input.pos();
+        while self.candidates.first().is_some_and(|(at, _)| *at < pos) {
+            self.candidates.pop_first();
+        }
+
+        let Some(start) = self.find_start(input)? else {
+            return Ok(None);
+        };
+        input.advance(start - input.pos())?;
+        Ok(Some(start))
+    }
+
+    fn matches_here<IF: MatchInput>(
+        &mut self,
+        input: &mut IF,
+        found: &mut VecDeque<(usize, Match)>,
+    ) -> RbrepResult<()> {
+        let start = input.pos();
+        let mut patterns = self.set.always.clone();
+        while let Some(&(at, pattern)) = self.candidates.first() {
+            if at != start {
+                break;
+            }
+            patterns.push(pattern);
+            self.candidates.pop_first();
+        }
+        patterns.sort_unstable();
+        patterns.dedup();
+        // patterns that start with ^ only match at the start of the input
+        patterns.retain(|p| start == 0 || !self.set.matchers[*p].anchored());
+
+        for pattern in patterns {
+            let cache = self.caches[pattern].as_mut();
+            if let Some(m) = self.set.matchers[pattern].match_here(input, cache)? {
+                found.push_back((pattern, m));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SetSearcher<'_> {
+    // the next offset at which any pattern may start
+    fn find_start<IF: MatchInput>(&mut self, input: &mut IF) -> RbrepResult<Option<usize>> {
+        loop {
+            if input.eof() {
+                return Ok(None);
+            }
+
+            // every candidate before this offset is known
+            let known = if self.scanned_all {
+                usize::MAX
+            } else {
+                self.scanned.saturating_sub(self.set.max_offset)
+            };
+
+            let pos = input.pos();
+            if !self.set.always.is_empty() && pos < known {
+                return Ok(Some(pos));
+            }
+            if let Some(&(at, _)) = self.candidates.first() {
+                if at < known {
+                    return Ok(Some(at));
+                }
+            }
+            if self.scanned_all {
+                return Ok(None);
+            }
+
+            // nothing can start before known
+            if self.candidates.is_empty() && self.set.always.is_empty() && known > pos {
+                input.advance(known - pos)?;
+            }
+            self.scan(input)?;
+        }
+    }
+
+    // find the literals in the next window of the input
+    fn scan<IF: MatchInput>(&mut self, input: &mut IF) -> RbrepResult<()> {
+        let Some(literals) = &self.set.literals else {
+            self.scanned_all = true;
+            return Ok(());
+        };
+
+        let pos = input.pos();
+        let from = self.scanned.max(pos) - pos;
+        let len = from + DEFAULT_CHUNK_SIZE.max(self.set.max_literal);
+        // the window may hold the whole rest of the input,
+        // every literal that is found is kept until its offset is reached
+        let window = input.window(len)?;
+        let window = &window[..window.len().min(len)];
+
+        for lit in literals.find_overlapping_iter(Input::new(window).span(from..window.len())) {
+            let (pattern, offset) = self.set.owners[lit.pattern().as_usize()];
+            let Some(at) = (pos + lit.start()).checked_sub(offset) else {
+                continue;
+            };
+            if at >= pos {
+                self.candidates.insert((at, pattern));
+            }
+        }
+
+        if window.len() < len {
+            self.scanned_all = true;
+        } else {
+            // literals that start in the last few bytes may end after the window
+            self.scanned = pos + window.len() - (self.set.max_literal - 1);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn find(patterns: &[(&str, &str)], input: &[u8], chunk_size: usize) -> Vec<(usize, usize)> {
+        let patterns: Vec<Pattern> = patterns.iter().map(|(n, e)| Pattern::new(n, e)).collect();
+        let set = MatcherSet::new(&patterns).unwrap();
+        set.find_iter_input(FileBufferInput::with_chunk_size(input, chunk_size))
+            .map(|m| m.map(|(pattern, m)| (m.start(), pattern)))
+            .collect::<RbrepResult<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn same_as_single() {
+        let patterns = [
+            ("mz", "\"MZ\" ??{0,4} \"PE\""),
+            ("elf", "?? \"ELF\""),
+            ("any", "30 ??"),
+            ("pe", "\"PE\""),
+        ];
+        let input = b"xMZabPE\x7fELF0MZPE00ELF";
+
+        let mut expected = vec![];
+        for (i, (_, expr)) in patterns.iter().enumerate() {
+            for m in Matcher::new(expr).unwrap().find_iter(input.as_slice()) {
+                expected.push((m.unwrap().start(), i));
+            }
+        }
+        expected.sort();
+
+        for chunk_size in [1, 3, 7, 64] {
+            assert_eq!(expected, find(&patterns, input, chunk_size));
+        }
+    }
+
+    #[test]
+    fn literals_only() {
+        let input = b"aaBBaBBBxBB";
+        assert_eq!(
+            vec![(2, 1), (5, 1), (6, 1), (7, 0), (9, 1)],
+            find(&[("x", "?? 78"), ("b", "42 42")], input, 2)
+        );
+    }
+
+    #[test]
+    fn pattern_file() {
+        let src = r#"
+# not a pattern
+"MZ" # unnamed
+pe = "PE" 00 00 # the header
+     # with a comment in between
+     4c 01
+elf=7f "ELF#=" # comments do not start in strings
+
+empty =
+"#;
+        let patterns = Pattern::parse_list(src).unwrap();
+        let names: Vec<&str> = patterns.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(vec!["\"MZ\"", "pe", "elf", "empty"], names);
+
+        let set = MatcherSet::new(&patterns[..3]).unwrap();
+        assert_eq!(6, set.matchers()[1].min_len());
+        assert_eq!(6, set.matchers()[2].min_len());
+
+        let err = MatcherSet::new(&patterns).err().unwrap();
+        assert_eq!("pattern empty: Empty expression", format!("{err:#}"));
+
+        // a # in a string does not end the name
+        let patterns = Pattern::parse_list(r##""a\"#" 00 # the name ends here"##).unwrap();
+        assert_eq!(r##""a\"#" 00"##, patterns[0].name);
+
+        let src = "$riff = \"RIFF\" ??*4;\nwav = $riff \"WAVE\"\navi = $riff \"AVI \"";
+        let patterns = Pattern::parse_list(src).unwrap();
+        let set = MatcherSet::new(&patterns).unwrap();
+        assert_eq!(2, set.len());
+        assert_eq!(12, set.matchers()[1].min_len());
+
+        // only a definition is shared, a reference is a pattern of its own
+        let src = "$riff = \"RIFF\" ??*4;\n$riff \"WAVE\"\navi = $riff \"AVI \"";
+        let patterns = Pattern::parse_list(src).unwrap();
+        assert_eq!(2, patterns.len());
+        assert_eq!("$riff \"WAVE\"", patterns[0].name);
+        let set = MatcherSet::new(&patterns).unwrap();
+        assert_eq!(12, set.matchers()[0].min_len());
+
+        let err = Pattern::parse_list("a = 30\na = 31").unwrap_err();
+        assert_eq!("pattern a is declared twice", err.to_string());
+    }
+
+    #[test]
+    fn output() {
+        let set = MatcherSet::new(&[Pattern::new("a", "30"), Pattern::new("b", "31")]).unwrap();
+        let mut o = vec![];
+        set.apply(
+            &mut "010".as_bytes(),
+            &mut o,
+            "stdin",
+            &SearchOptions::default(),
+            &OutputOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            "stdin\n00000000\ta\t30\n00000001\tb\t31\n00000002\ta\t30\n",
+            String::from_utf8(o).unwrap()
+        );
+
+        let mut o = vec![];
+        set.apply(
+            &mut "01".as_bytes(),
+            &mut o,
+            "stdin",
+            &SearchOptions::default(),
+            &OutputOptions {
+                json: true,
+                count: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            "{\"file\":\"stdin\",\"count\":2}\n",
+            String::from_utf8(o).unwrap()
+        );
+
+        let aligned: Vec<(usize, usize)> = set
+            .find_iter("0101".as_bytes())
+            .aligned(2)
+            .map(|m| m.map(|(pattern, m)| (m.start(), pattern)))
+            .collect::<RbrepResult<_>>()
+            .unwrap();
+        assert_eq!(vec![(0, 0), (2, 0)], aligned);
+
+        let err = MatcherSet::new(&[Pattern::new("a", "30"), Pattern::new("bad", "3z")])
+            .err()
+            .unwrap();
+        assert!(format!("{err:#}").starts_with("pattern bad: "));
+    }
+}
@@ -0,0 +1,169 @@
+use std::path::{Path, PathBuf};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use walkdir::WalkDir;
+
+use crate::core::options::WalkOptions;
+
+// include and exclude globs
+// a glob matches either the file name or the entire path
+struct Filter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl Filter {
+    fn new(opts: &WalkOptions) -> anyhow::Result<Self> {
+        let include = if opts.include.is_empty() {
+            None
+        } else {
+            Some(Self::build(&opts.include)?)
+        };
+
+        Ok(Self {
+            include,
+            exclude: Self::build(&opts.exclude)?,
+        })
+    }
+
+    fn build(globs: &[String]) -> anyhow::Result<GlobSet> {
+        let mut builder = GlobSetBuilder::new();
+        for glob in globs {
+            builder.add(Glob::new(glob)?);
+        }
+        Ok(builder.build()?)
+    }
+
+    fn matches(set: &GlobSet, path: &Path) -> bool {
+        path.file_name().is_some_and(|name| set.is_match(name)) || set.is_match(path)
+    }
+
+    fn is_excluded(&self, path: &Path) -> bool {
+        Self::matches(&self.exclude, path)
+    }
+
+    fn is_included(&self, path: &Path) -> bool {
+        self.include
+            .as_ref()
+            .is_none_or(|include| Self::matches(include, path))
+            && !self.is_excluded(path)
+    }
+}
+
+impl WalkOptions {
+    // call each for every file that should be searched
+    // until each returns false.
+    // directories are only expanded when searching recursively,
+    // any other path is passed on as is
+    pub fn for_each_file<CB>(&self, paths: &[PathBuf], each: &mut CB) -> anyhow::Result<()>
+    where
+        CB: FnMut(anyhow::Result<PathBuf>) -> anyhow::Result<bool>,
+    {
+        let filter = Filter::new(self)?;
+
+        for path in paths {
+            if !self.recursive || !path.is_dir() {
+                if !each(Ok(path.clone()))? {
+                    return Ok(());
+                }
+                continue;
+            }
+
+            let mut walk = WalkDir::new(path)
+                .follow_links(self.follow)
+                .max_depth(self.max_depth.unwrap_or(usize::MAX))
+                .sort_by_file_name()
+                .into_iter();
+
+            while let Some(entry) = walk.next() {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(err) => {
+                        if !each(Err(err.into()))? {
+                            return Ok(());
+                        }
+                        continue;
+                    }
+                };
+
+                if entry.file_type().is_dir() {
+                    if entry.depth() > 0 && filter.is_excluded(entry.path()) {
+                        walk.skip_current_dir();
+                    }
+                } else if entry.file_type().is_file() && filter.is_included(entry.path()) {
+                    // only regular files are searched, this skips
+                    // symlinks that are not followed, devices, pipes and sockets
+                    if !each(Ok(entry.into_path()))? {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    fn walk(root: &Path, opts: WalkOptions) -> Vec<String> {
+        let mut files = vec![];
+        opts.for_each_file(&[root.to_path_buf()], &mut |path| {
+            let path = path?;
+            files.push(
+                path.strip_prefix(root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned(),
+            );
+            Ok(true)
+        })
+        .unwrap();
+        files
+    }
+
+    #[test]
+    fn recursive() {
+        let root = std::env::temp_dir().join(format!("rbrep-walk-{}", std::process::id()));
+        create_dir_all(root.join("a/b")).unwrap();
+        create_dir_all(root.join("skip")).unwrap();
+        write(root.join("1.bin"), "").unwrap();
+        write(root.join("a/2.exe"), "").unwrap();
+        write(root.join("a/b/3.bin"), "").unwrap();
+        write(root.join("skip/4.bin"), "").unwrap();
+
+        let all = walk(
+            &root,
+            WalkOptions {
+                recursive: true,
+                ..Default::default()
+            },
+        );
+        let filtered = walk(
+            &root,
+            WalkOptions {
+                recursive: true,
+                include: vec!["*.bin".into()],
+                exclude: vec!["skip".into()],
+                ..Default::default()
+            },
+        );
+        let shallow = walk(
+            &root,
+            WalkOptions {
+                recursive: true,
+                max_depth: Some(2),
+                ..Default::default()
+            },
+        );
+        let flat = walk(&root, WalkOptions::default());
+        remove_dir_all(&root).unwrap();
+
+        assert_eq!(vec!["1.bin", "a/2.exe", "a/b/3.bin", "skip/4.bin"], all);
+        assert_eq!(vec!["1.bin", "a/b/3.bin"], filtered);
+        assert_eq!(vec!["1.bin", "a/2.exe", "skip/4.bin"], shallow);
+        assert_eq!(vec![""], flat);
+    }
+}
@@ -3,14 +3,19 @@ fn main() {}
 
 #[cfg(feature = "cli")]
 fn main() {
-    use rbrep::{core::config::generate_completion, prelude::exec, prelude::CFG};
-    if let Some(shell) = CFG.completions {
+    use rbrep::{core::config::generate_completion, prelude::exec, prelude::Config};
+    let cfg = Config::new();
+    if let Some(shell) = cfg.completions {
         generate_completion(shell);
         std::process::exit(0);
     }
 
-    if let Err(error) = exec() {
-        println!("{error}");
+    match exec(&cfg) {
+        Ok(summary) => std::process::exit(summary.exit_code(cfg.quiet)),
+        Err(error) => {
+            eprintln!("rbrep: {error:#}");
+            std::process::exit(2);
+        }
     }
 }
 
@@ -1,4 +1,9 @@
-pub use crate::core::config::{Config, CFG};
-pub use crate::core::error::{Error, RbrepResult};
-pub use crate::core::expr::{exec, Expr, ExprBranch};
+pub use crate::core::config::Config;
+pub use crate::core::error::{Error, RbrepResult, SyntaxError};
+pub use crate::core::exec::exec;
+pub use crate::core::expr::{Expr, ExprBranch};
+pub use crate::core::matcher::{Matcher, Matches};
+pub use crate::core::options::{OutputOptions, SearchOptions};
+pub use crate::core::output::{Capture, CaptureFormat, Match};
 pub use crate::core::parser::Parser;
+pub use crate::core::set::{MatcherSet, Pattern};